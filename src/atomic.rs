@@ -0,0 +1,818 @@
+//! A lock-free hash map, after Shalev and Shavit's split-ordered lists.
+//!
+//! All entries live in a single linked list, sorted by the bit-reversed
+//! hash of their key. A bucket is a pointer to a dummy node in that list,
+//! standing right before the entries whose hash ends in the bucket's
+//! index. Doubling the number of buckets only splits each bucket's run of
+//! the list in two, at a new dummy node linked in on first use: no entry
+//! ever moves.
+//!
+//! Removal follows Harris and Michael: a node is first marked, in the low
+//! bit of its `next` pointer, and then unlinked by whoever comes across it.
+//! Unlinked nodes are freed through `crossbeam_epoch` once no thread can
+//! still be reading them.
+
+use std::array;
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::Deref;
+use std::ptr;
+use std::sync::atomic::{AtomicIsize, AtomicPtr, AtomicUsize};
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed};
+
+use crossbeam_epoch::{self as epoch, Atomic, Guard, Owned, Shared};
+
+use crate::raw;
+
+
+/// Number of buckets of a new map.
+const MIN_BUCKETS: usize = 2;
+/// Average number of entries per bucket past which the buckets double.
+const MAX_LOAD: usize = 2;
+const MAX_BUCKETS: usize = 1 << (usize::BITS - 1);
+/// Bucket 0 has a segment of its own, and segment `i > 0` holds buckets
+/// `2^(i-1)..2^i`.
+const SEGMENTS: usize = usize::BITS as usize + 1;
+
+
+/// A pointer to the next node of the list, or to the first node of a
+/// bucket.
+type Link<K, V> = Atomic<Node<K, V>>;
+
+
+struct Node<K, V> {
+    /// Position in the list: even for the dummy node starting a bucket, odd
+    /// for an entry.
+    so_key: u64,
+    /// `None` for dummy nodes.
+    key: Option<K>,
+    /// Null for dummy nodes. Tagged once the entry is removed, after which
+    /// it is never replaced.
+    value: Atomic<V>,
+    /// Tagged once the node is removed, after which it is never changed.
+    next: Link<K, V>,
+}
+
+
+impl<K, V> Node<K, V> {
+    fn dummy(so_key: u64) -> Self {
+        Node { so_key, key: None, value: Atomic::null(), next: Atomic::null() }
+    }
+
+    /// Whether this is the entry of `key`, and not removed yet.
+    fn holds<Q>(&self, key: &Q, guard: &Guard) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        self.key.as_ref().is_some_and(|k| k.borrow() == key) && self.value.load(Acquire, guard).tag() == 0
+    }
+}
+
+
+impl<K, V> Drop for Node<K, V> {
+    fn drop(&mut self) {
+        // SAFETY: a node is only dropped once unreachable, along with the
+        // value it still points to.
+        unsafe {
+            let value = self.value.load(Relaxed, epoch::unprotected());
+            if !value.is_null() {
+                drop(value.with_tag(0).into_owned());
+            }
+        }
+    }
+}
+
+
+/// The position in the list of an entry whose `hash` has its bits mixed
+/// into the low ones: bit-reversed, with the lowest bit set to sort it
+/// after the dummy node of its bucket.
+fn regular_key(hash: u64) -> u64 {
+    (hash | 1 << 63).reverse_bits()
+}
+
+
+/// The position in the list of the dummy node starting bucket `index`.
+fn dummy_key(index: usize) -> u64 {
+    (index as u64).reverse_bits()
+}
+
+
+/// A hash map which many threads can read and modify at once without
+/// taking any lock.
+///
+/// Every method takes `&self`. `insert`, `get` and `remove` never wait for
+/// another thread: they retry when one gets in their way, and help it along
+/// where they can. Growing the map only publishes more buckets, which
+/// threads then link in as they need them.
+///
+/// `get`, and `insert` and `remove` for the value they replace or remove,
+/// return a `Ref`. A `Ref` keeps its entry readable even once it leaves the
+/// map, by holding back the reclamation of memory until it is dropped.
+pub struct AtomicHashMap<K, V, S = RandomState> {
+    /// Lazily allocated arrays of buckets, never moved nor freed before the
+    /// map, each pointing to its dummy node once that is linked in.
+    segments: [AtomicPtr<Link<K, V>>; SEGMENTS],
+    /// Number of buckets in use, a power of two.
+    size: AtomicUsize,
+    /// Number of entries, counted after an insertion and before a removal:
+    /// it may lag below the actual number, and even below zero, but never
+    /// runs ahead of it.
+    len: AtomicIsize,
+    hash_builder: S,
+    marker: PhantomData<Node<K, V>>,
+}
+
+
+// SAFETY: entries are read and dropped by whichever thread gets to them.
+unsafe impl<K: Send + Sync, V: Send + Sync, S: Send> Send for AtomicHashMap<K, V, S> {}
+unsafe impl<K: Send + Sync, V: Send + Sync, S: Sync> Sync for AtomicHashMap<K, V, S> {}
+
+
+impl<K, V> AtomicHashMap<K, V, RandomState> {
+    pub fn new() -> Self {
+        AtomicHashMap::with_hasher(RandomState::new())
+    }
+
+    /// Creates an empty map with buckets for `capacity` entries.
+    pub fn with_capacity(capacity: usize) -> Self {
+        AtomicHashMap::with_capacity_and_hasher(capacity, RandomState::new())
+    }
+}
+
+
+/// Number of buckets to hold `capacity` entries without growing.
+fn buckets_for(capacity: usize) -> usize {
+    (capacity / MAX_LOAD).checked_next_power_of_two().expect("capacity overflow").clamp(MIN_BUCKETS, MAX_BUCKETS)
+}
+
+
+impl<K, V, S> AtomicHashMap<K, V, S> {
+    /// Creates an empty map which will use `hash_builder` to hash keys.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        let map = AtomicHashMap {
+            segments: array::from_fn(|_| AtomicPtr::new(ptr::null_mut())),
+            size: AtomicUsize::new(MIN_BUCKETS),
+            len: AtomicIsize::new(0),
+            hash_builder,
+            marker: PhantomData,
+        };
+        // The dummy node of bucket 0 heads the list, so it is there from the
+        // start.
+        map.slot(0).store(Owned::new(Node::dummy(0)), Relaxed);
+        map
+    }
+
+    /// Creates an empty map with buckets for `capacity` entries, which will
+    /// use `hash_builder` to hash keys.
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
+        let map = AtomicHashMap::with_hasher(hash_builder);
+        map.reserve(capacity);
+        map
+    }
+
+    /// Returns a reference to the map's `BuildHasher`.
+    pub fn hasher(&self) -> &S {
+        &self.hash_builder
+    }
+
+    /// Number of entries. Other threads may change it right away.
+    pub fn len(&self) -> usize {
+        self.len.load(Relaxed).max(0) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of buckets in use. Other threads may double it right away.
+    pub fn bucket_count(&self) -> usize {
+        self.size.load(Acquire)
+    }
+
+    /// Makes room for at least `additional` more entries before the buckets
+    /// next double. The buckets are allocated right away, but each new one
+    /// is only linked into the list when first used, and no entry moves.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buckets can't be allocated.
+    pub fn reserve(&self, additional: usize) {
+        let capacity = self.len().checked_add(additional).expect("capacity overflow");
+        let nbuckets = buckets_for(capacity);
+        // Largest first, so that a size too big for memory fails before
+        // allocating anything.
+        for segment in (1..=nbuckets.trailing_zeros()).rev() {
+            self.slot(1 << (segment - 1));
+        }
+        self.size.fetch_max(nbuckets, AcqRel);
+    }
+
+    /// Returns the pointer of bucket `index`, allocating its segment if need
+    /// be.
+    fn slot(&self, index: usize) -> &Link<K, V> {
+        let segment = (usize::BITS - index.leading_zeros()) as usize;
+        let start = if segment == 0 { 0 } else { 1 << (segment - 1) };
+        let mut buckets = self.segments[segment].load(Acquire);
+        if buckets.is_null() {
+            let new = new_segment(start.max(1));
+            match self.segments[segment].compare_exchange(ptr::null_mut(), new, AcqRel, Acquire) {
+                Ok(_) => buckets = new,
+                Err(current) => {
+                    // SAFETY: `new` was never shared.
+                    unsafe { free_segment(new, start.max(1)) };
+                    buckets = current;
+                }
+            }
+        }
+        // SAFETY: segment `segment` holds `start.max(1)` buckets, from
+        // `start` on.
+        unsafe { &*buckets.add(index - start) }
+    }
+
+    /// Visits the entries still in the map, in no particular order.
+    fn for_each<F: FnMut(&K, &V)>(&self, mut f: F) {
+        let guard = epoch::pin();
+        let mut node = self.slot(0).load(Acquire, &guard);
+        // SAFETY: nodes reachable from the list while pinned aren't freed
+        // before `guard` is dropped.
+        while let Some(n) = unsafe { node.as_ref() } {
+            let value = n.value.load(Acquire, &guard);
+            if let (Some(key), Some(value), 0) = (&n.key, unsafe { value.as_ref() }, value.tag()) {
+                f(key, value);
+            }
+            node = n.next.load(Acquire, &guard);
+        }
+    }
+}
+
+
+/// Panics, rather than aborts, if the memory isn't there.
+fn new_segment<T>(len: usize) -> *mut Atomic<T> {
+    let mut buckets: Vec<Atomic<T>> = Vec::new();
+    buckets.try_reserve_exact(len).unwrap_or_else(|err| panic!("{}", err));
+    buckets.extend((0..len).map(|_| Atomic::null()));
+    Box::into_raw(buckets.into_boxed_slice()) as *mut Atomic<T>
+}
+
+
+/// # Safety
+///
+/// `buckets` must come from `new_segment(len)`, and not be used afterwards.
+unsafe fn free_segment<T>(buckets: *mut Atomic<T>, len: usize) {
+    drop(Box::from_raw(ptr::slice_from_raw_parts_mut(buckets, len)));
+}
+
+
+/// Walks the list from `start` to the first node past `so_key`, unlinking
+/// the removed nodes along the way.
+///
+/// Returns the link where the walk stopped, the node it points to, and
+/// whether that node is at `so_key` and `matches`. If not, a node at
+/// `so_key` belongs right there.
+fn find<'g, K, V, F>(
+    start: &'g Link<K, V>,
+    so_key: u64,
+    matches: F,
+    guard: &'g Guard,
+) -> (&'g Link<K, V>, Shared<'g, Node<K, V>>, bool)
+where
+    F: Fn(&Node<K, V>) -> bool,
+{
+    'retry: loop {
+        let mut prev = start;
+        let mut curr = prev.load(Acquire, guard);
+        // SAFETY: nodes reachable from the list while pinned aren't freed
+        // before `guard` is dropped.
+        while let Some(node) = unsafe { curr.as_ref() } {
+            let next = node.next.load(Acquire, guard);
+            if next.tag() != 0 {
+                match prev.compare_exchange(curr, next.with_tag(0), AcqRel, Acquire, guard) {
+                    Ok(_) => {
+                        // SAFETY: `curr` is unlinked, by this thread only.
+                        unsafe { guard.defer_destroy(curr) };
+                        curr = next.with_tag(0);
+                        continue;
+                    }
+                    // `prev` was removed or relinked in the meantime.
+                    Err(_) => continue 'retry,
+                }
+            }
+            if node.so_key > so_key {
+                break;
+            }
+            if node.so_key == so_key && matches(node) {
+                return (prev, curr, true);
+            }
+            prev = &node.next;
+            curr = next;
+        }
+        return (prev, curr, false);
+    }
+}
+
+
+impl<K, V, S> AtomicHashMap<K, V, S>
+where
+  K: Hash + Eq + Send + 'static,
+  V: Send + 'static,
+  S: BuildHasher,
+{
+  fn hash<Q: Hash + ?Sized>(&self, key: &Q) -> u64 {
+    // The top bits of the product are the best mixed: reverse them to the
+    // bottom, where buckets are picked from.
+    self.hash_builder.hash_one(key).wrapping_mul(raw::FIBONACCI).reverse_bits()
+  }
+
+  /// Returns the dummy node starting the bucket of `hash`.
+  fn bucket_of<'g>(&'g self, hash: u64, guard: &'g Guard) -> &'g Node<K, V> {
+    self.bucket(hash as usize & (self.bucket_count() - 1), guard)
+  }
+
+  /// Returns the dummy node starting bucket `index`, linking it in first if
+  /// no thread has yet.
+  fn bucket<'g>(&'g self, index: usize, guard: &'g Guard) -> &'g Node<K, V> {
+    let slot = self.slot(index);
+    // SAFETY: dummy nodes are only freed along with the map.
+    if let Some(dummy) = unsafe { slot.load(Acquire, guard).as_ref() } {
+      return dummy;
+    }
+    // Bucket 0 is always there, and any other one split off the bucket
+    // whose index is its own but for the top bit.
+    let parent = self.bucket(index & !(1 << index.ilog2()), guard);
+    let so_key = dummy_key(index);
+    let mut new = Owned::new(Node::dummy(so_key));
+    let dummy = loop {
+      let (prev, curr, found) = find(&parent.next, so_key, |node| node.key.is_none(), guard);
+      if found {
+        break curr;
+      }
+      new.next.store(curr, Relaxed);
+      match prev.compare_exchange(curr, new, AcqRel, Acquire, guard) {
+        Ok(dummy) => break dummy,
+        Err(err) => new = err.new,
+      }
+    };
+    // Any other thread setting the slot found this same node.
+    let _ = slot.compare_exchange(Shared::null(), dummy, AcqRel, Acquire, guard);
+    // SAFETY: as above.
+    unsafe { dummy.deref() }
+  }
+
+  /// Doubles the buckets if the entries got too many for them.
+  fn grow(&self) {
+    let size = self.bucket_count();
+    if size < MAX_BUCKETS && self.len() / MAX_LOAD > size {
+      // If another thread got there first, the buckets grew anyway.
+      let _ = self.size.compare_exchange(size, size * 2, AcqRel, Acquire);
+    }
+  }
+
+  /// Inserts `value` under `key`, and returns the value it replaces.
+  pub fn insert(&self, key: K, value: V) -> Option<Ref<'_, K, V>> {
+    let guard = epoch::pin();
+    let hash = self.hash(&key);
+    let so_key = regular_key(hash);
+    let bucket = self.bucket_of(hash, &guard);
+    let mut node = Owned::new(Node { so_key, key: Some(key), value: Atomic::null(), next: Atomic::null() });
+    let mut value = Owned::new(value);
+    loop {
+      let key = node.key.as_ref().unwrap();
+      let (prev, curr, found) = find(&bucket.next, so_key, |n| n.holds(key, &guard), &guard);
+      if found {
+        // SAFETY: as in `find`.
+        let entry = unsafe { curr.deref() };
+        let old = entry.value.load(Acquire, &guard);
+        if old.tag() != 0 {
+          continue;
+        }
+        match entry.value.compare_exchange(old, value, AcqRel, Acquire, &guard) {
+          Ok(_) => {
+            // SAFETY: `old` is unreachable, replaced by this thread only.
+            unsafe { guard.defer_destroy(old) };
+            let (key, old) = (entry.key.as_ref().unwrap() as *const K, old.as_raw());
+            return Some(Ref::new(guard, key, old));
+          }
+          Err(err) => {
+            value = err.new;
+            continue;
+          }
+        }
+      }
+      node.value = Atomic::from(value);
+      node.next.store(curr, Relaxed);
+      match prev.compare_exchange(curr, node, AcqRel, Acquire, &guard) {
+        Ok(_) => break,
+        Err(err) => {
+          node = err.new;
+          // SAFETY: `node` was never shared.
+          value = unsafe { mem::replace(&mut node.value, Atomic::null()).into_owned() };
+        }
+      }
+    }
+    self.len.fetch_add(1, Relaxed);
+    self.grow();
+    None
+  }
+
+  /// Returns a guard through which the value of `key` can be read, even
+  /// once another thread removes or replaces it.
+  pub fn get<Q>(&self, key: &Q) -> Option<Ref<'_, K, V>>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let guard = epoch::pin();
+    let hash = self.hash(key);
+    let bucket = self.bucket_of(hash, &guard);
+    let (_, curr, found) = find(&bucket.next, regular_key(hash), |n| n.holds(key, &guard), &guard);
+    if !found {
+      return None;
+    }
+    // SAFETY: as in `find`.
+    let entry = unsafe { curr.deref() };
+    let value = entry.value.load(Acquire, &guard);
+    if value.tag() != 0 {
+      return None;
+    }
+    let (key, value) = (entry.key.as_ref().unwrap() as *const K, value.as_raw());
+    Some(Ref::new(guard, key, value))
+  }
+
+  pub fn contains_key<Q>(&self, key: &Q) -> bool
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    self.get(key).is_some()
+  }
+
+  /// Removes `key`, and returns a guard through which its entry can still
+  /// be read.
+  pub fn remove<Q>(&self, key: &Q) -> Option<Ref<'_, K, V>>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let guard = epoch::pin();
+    let hash = self.hash(key);
+    let so_key = regular_key(hash);
+    let bucket = self.bucket_of(hash, &guard);
+    let (key, value) = loop {
+      let (prev, curr, found) = find(&bucket.next, so_key, |n| n.holds(key, &guard), &guard);
+      if !found {
+        return None;
+      }
+      // SAFETY: as in `find`.
+      let entry = unsafe { curr.deref() };
+      let value = entry.value.load(Acquire, &guard);
+      if value.tag() != 0 {
+        continue;
+      }
+      // Uncount the entry before it goes, so that `len` never counts it
+      // once removed.
+      self.len.fetch_sub(1, Relaxed);
+      if entry.value.compare_exchange(value, value.with_tag(1), AcqRel, Acquire, &guard).is_err() {
+        self.len.fetch_add(1, Relaxed);
+        continue;
+      }
+      // Tagging the value removed the entry; mark the node for unlinking,
+      // and unlink it unless another thread is in the way.
+      let next = entry.next.fetch_or(1, AcqRel, &guard);
+      match prev.compare_exchange(curr, next, AcqRel, Acquire, &guard) {
+        // SAFETY: `curr` is unlinked, by this thread only.
+        Ok(_) => unsafe { guard.defer_destroy(curr) },
+        Err(_) => {
+          find(&bucket.next, so_key, |_| false, &guard);
+        }
+      }
+      break (entry.key.as_ref().unwrap() as *const K, value.as_raw());
+    };
+    Some(Ref::new(guard, key, value))
+  }
+}
+
+
+impl<K, V, S> Drop for AtomicHashMap<K, V, S> {
+    fn drop(&mut self) {
+        // SAFETY: no other thread can reach the map anymore, and the nodes
+        // still in the list aren't pending reclamation.
+        unsafe {
+            let guard = epoch::unprotected();
+            let mut node = self.slot(0).load(Relaxed, guard);
+            while !node.is_null() {
+                let next = node.deref().next.load(Relaxed, guard);
+                drop(node.into_owned());
+                node = next.with_tag(0);
+            }
+            for (segment, buckets) in self.segments.iter().enumerate() {
+                let buckets = buckets.load(Relaxed);
+                if !buckets.is_null() {
+                    free_segment(buckets, if segment == 0 { 1 } else { 1 << (segment - 1) });
+                }
+            }
+        }
+    }
+}
+
+
+impl<K, V> Default for AtomicHashMap<K, V, RandomState> {
+    fn default() -> Self {
+        AtomicHashMap::new()
+    }
+}
+
+
+impl<K, V, S> fmt::Debug for AtomicHashMap<K, V, S>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut map = f.debug_map();
+        self.for_each(|k, v| {
+            map.entry(k, v);
+        });
+        map.finish()
+    }
+}
+
+
+/// An entry of an `AtomicHashMap`, which derefs to its value. The entry
+/// can be read for as long as the `Ref` lives, whatever happens to it in
+/// the map meanwhile.
+pub struct Ref<'a, K: 'a, V: 'a> {
+    /// Keeps `key` and `value` from being freed.
+    _guard: Guard,
+    key: *const K,
+    value: *const V,
+    marker: PhantomData<&'a (K, V)>,
+}
+
+
+impl<'a, K, V> Ref<'a, K, V> {
+    fn new(guard: Guard, key: *const K, value: *const V) -> Self {
+        Ref { _guard: guard, key, value, marker: PhantomData }
+    }
+
+    pub fn key(&self) -> &K {
+        // SAFETY: the node of `key` isn't freed while `_guard` is pinned.
+        unsafe { &*self.key }
+    }
+
+    pub fn value(&self) -> &V {
+        // SAFETY: nor is `value`.
+        unsafe { &*self.value }
+    }
+}
+
+
+impl<'a, K, V> Deref for Ref<'a, K, V> {
+    type Target = V;
+    fn deref(&self) -> &V {
+        self.value()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rng::Rng;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::thread;
+
+    #[test]
+    fn insert() {
+        let map = AtomicHashMap::new();
+        assert!(map.insert(1, "a").is_none());
+        assert_eq!(*map.insert(1, "b").unwrap(), "a");
+        assert_eq!(map.len(), 1);
+        assert_eq!(*map.get(&1).unwrap(), "b");
+    }
+
+    #[test]
+    fn get() {
+        let map = AtomicHashMap::new();
+        map.insert(String::from("foo"), 1);
+        let value = map.get("foo").unwrap();
+        assert_eq!(value.key(), "foo");
+        assert_eq!(*value, 1);
+        assert!(map.get("bar").is_none());
+    }
+
+    #[test]
+    fn remove() {
+        let map = AtomicHashMap::new();
+        map.insert(1, String::from("a"));
+        let removed = map.remove(&1).unwrap();
+        assert!(map.remove(&1).is_none());
+        assert!(!map.contains_key(&1));
+        assert!(map.is_empty());
+        // Still readable through the guard.
+        assert_eq!(*removed, "a");
+        assert_eq!(*removed.key(), 1);
+    }
+
+    #[test]
+    fn contains_key() {
+        let map = AtomicHashMap::new();
+        map.insert("a", 1);
+        assert!(map.contains_key("a"));
+        assert!(!map.contains_key("b"));
+    }
+
+    #[test]
+    fn grows_in_place() {
+        let map = AtomicHashMap::new();
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.len(), 1000);
+        assert!(map.bucket_count() >= 1000 / MAX_LOAD);
+        for i in 0..1000 {
+            assert_eq!(*map.get(&i).unwrap(), i);
+        }
+    }
+
+    #[test]
+    fn with_capacity() {
+        let map = AtomicHashMap::with_capacity(1000);
+        let buckets = map.bucket_count();
+        assert!(buckets >= 1000 / MAX_LOAD);
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.bucket_count(), buckets);
+        assert_eq!(AtomicHashMap::<u8, u8>::with_capacity(0).bucket_count(), MIN_BUCKETS);
+    }
+
+    #[test]
+    fn reserve() {
+        let map = AtomicHashMap::new();
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        map.reserve(1000);
+        let buckets = map.bucket_count();
+        assert!(buckets >= 1100 / MAX_LOAD);
+        // Allocated already, up to the last bucket.
+        let segments = buckets.trailing_zeros() as usize;
+        assert!(map.segments[..=segments].iter().all(|segment| !segment.load(Relaxed).is_null()));
+        assert!(map.segments[segments + 1..].iter().all(|segment| segment.load(Relaxed).is_null()));
+        for i in 100..1100 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.bucket_count(), buckets);
+        for i in 0..1100 {
+            assert_eq!(*map.get(&i).unwrap(), i);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn reserve_overflow() {
+        let map = AtomicHashMap::new();
+        map.insert(1, 1);
+        map.reserve(usize::MAX);
+    }
+
+    #[test]
+    fn reserve_too_large() {
+        let map = AtomicHashMap::new();
+        map.insert(1, 1);
+        let reserved = panic::catch_unwind(AssertUnwindSafe(|| map.reserve(usize::MAX / 2)));
+        assert!(reserved.is_err());
+        // Nothing changed, and the map still works.
+        assert_eq!(map.bucket_count(), MIN_BUCKETS);
+        map.insert(2, 2);
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn split_order() {
+        let map = AtomicHashMap::new();
+        for i in 0..500 {
+            map.insert(i, i);
+        }
+        for i in (0..500).step_by(3) {
+            map.remove(&i);
+        }
+        let guard = epoch::pin();
+        let mut keys = Vec::new();
+        let mut node = map.slot(0).load(Acquire, &guard);
+        while let Some(n) = unsafe { node.as_ref() } {
+            keys.push(n.so_key);
+            node = n.next.load(Acquire, &guard);
+        }
+        assert!(keys.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(keys.iter().filter(|&&k| k & 1 == 1).count(), map.len());
+    }
+
+    #[test]
+    fn matches_model() {
+        let map = AtomicHashMap::new();
+        let mut model = std::collections::HashMap::new();
+        let mut rng = Rng::new(1);
+        for _ in 0..5000 {
+            let (key, value) = (rng.below(256) as u32, rng.next() as u32);
+            if rng.below(3) == 0 {
+                assert_eq!(map.remove(&key).map(|v| *v), model.remove(&key));
+            } else {
+                assert_eq!(map.insert(key, value).map(|v| *v), model.insert(key, value));
+            }
+            assert_eq!(map.len(), model.len());
+        }
+        for (key, value) in &model {
+            assert_eq!(*map.get(key).unwrap(), *value);
+        }
+    }
+
+    #[test]
+    fn debug() {
+        let map = AtomicHashMap::new();
+        map.insert("a", 1);
+        assert_eq!(format!("{:?}", map), r#"{"a": 1}"#);
+    }
+
+    #[test]
+    fn threads() {
+        let map = AtomicHashMap::new();
+        thread::scope(|scope| {
+            for t in 0..8 {
+                let map = &map;
+                scope.spawn(move || {
+                    for i in 0..1000 {
+                        map.insert(t * 1000 + i, i);
+                    }
+                    for i in (0..1000).step_by(2) {
+                        assert_eq!(*map.remove(&(t * 1000 + i)).unwrap(), i);
+                    }
+                });
+            }
+        });
+        assert_eq!(map.len(), 4000);
+        for key in 0..8000 {
+            assert_eq!(map.get(&key).map(|v| *v), Some(key % 1000).filter(|v| v % 2 == 1));
+        }
+    }
+
+    #[test]
+    fn threads_contend() {
+        // Every thread inserts and removes the same few keys.
+        let map = AtomicHashMap::new();
+        thread::scope(|scope| {
+            for t in 0..8u32 {
+                let map = &map;
+                scope.spawn(move || {
+                    let mut rng = Rng::new(t.into());
+                    for _ in 0..5000 {
+                        let x = rng.next() as u32;
+                        let key = x % 16;
+                        if x.is_multiple_of(2) {
+                            map.remove(&key);
+                        } else {
+                            map.insert(key, x);
+                        }
+                        if let Some(value) = map.get(&key) {
+                            assert_eq!(*value % 16, key);
+                        }
+                    }
+                });
+            }
+        });
+        let present = (0..16).filter(|k| map.contains_key(k)).count();
+        assert_eq!(map.len(), present);
+    }
+
+    #[test]
+    fn len_stays_bounded() {
+        // Threads racing to insert and remove the same keys may only make
+        // `len` lag behind, never count an entry twice nor wrap around.
+        const KEYS: u32 = 4;
+        let map = AtomicHashMap::new();
+        thread::scope(|scope| {
+            for t in 0..4 {
+                let map = &map;
+                scope.spawn(move || {
+                    for i in 0..20_000 {
+                        let key = (i + t) % KEYS;
+                        if (i / KEYS + t).is_multiple_of(2) {
+                            map.insert(key, i);
+                        } else {
+                            map.remove(&key);
+                        }
+                        assert!(map.len() <= KEYS as usize);
+                    }
+                });
+            }
+        });
+        let present = (0..KEYS).filter(|k| map.contains_key(k)).count();
+        assert_eq!(map.len(), present);
+        assert_eq!(map.bucket_count(), MIN_BUCKETS);
+    }
+}
@@ -0,0 +1,464 @@
+//! A one-to-one map which can be looked up from either side.
+
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::iter::FromIterator;
+use std::slice;
+
+use crate::raw::RawTable;
+
+
+/// A one-to-one map between left values `L` and right values `R`.
+///
+/// Every left value is paired with exactly one right value and the other
+/// way round: inserting a pair removes any pair sharing either side with
+/// it. Pairs are stored once, in a dense vector, and two tables of
+/// indices into it are kept in sync, one hashed by left values and one
+/// by right values. Both are hashed with the `BuildHasher` `S`, and looked
+/// up through `Borrow` like `HashMap::get`.
+pub struct BiHashMap<L, R, S = RandomState> {
+    pairs: Vec<(L, R)>,
+    /// Indices into `pairs`, hashed by left value.
+    left: RawTable<usize>,
+    /// Indices into `pairs`, hashed by right value.
+    right: RawTable<usize>,
+    hash_builder: S,
+}
+
+
+/// The pairs an insert into a `BiHashMap` removed to keep it one-to-one.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Overwritten<L, R> {
+    /// Neither value was in the map.
+    Neither,
+    /// The left value was paired with another right value.
+    Left(L, R),
+    /// The right value was paired with another left value.
+    Right(L, R),
+    /// The very same pair was in the map.
+    Pair(L, R),
+    /// Both values were paired with others: the pair sharing the left
+    /// value, then the one sharing the right value.
+    Both((L, R), (L, R)),
+}
+
+
+impl<L, R> BiHashMap<L, R, RandomState> {
+    pub fn new() -> Self {
+        BiHashMap::with_hasher(RandomState::new())
+    }
+}
+
+
+impl<L, R, S> BiHashMap<L, R, S> {
+    /// Creates an empty map which will use `hash_builder` to hash both
+    /// sides.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        BiHashMap {
+            pairs: Vec::new(),
+            left: RawTable::new(),
+            right: RawTable::new(),
+            hash_builder,
+        }
+    }
+
+    /// Creates an empty map able to hold `capacity` pairs without
+    /// resizing, which will use `hash_builder` to hash both sides.
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
+        BiHashMap {
+            pairs: Vec::with_capacity(capacity),
+            left: RawTable::with_capacity(capacity),
+            right: RawTable::with_capacity(capacity),
+            hash_builder,
+        }
+    }
+
+    /// Returns a reference to the map's `BuildHasher`.
+    pub fn hasher(&self) -> &S {
+        &self.hash_builder
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    pub fn iter(&self) -> Iter<'_, L, R> {
+        Iter { inner: self.pairs.iter() }
+    }
+
+    /// Removes every pair, keeping the allocated memory.
+    pub fn clear(&mut self) {
+        self.pairs.clear();
+        self.left.clear();
+        self.right.clear();
+    }
+}
+
+
+impl<L, R, S> BiHashMap<L, R, S>
+where
+    L: Hash + Eq,
+    R: Hash + Eq,
+    S: BuildHasher,
+{
+  /// Index in `pairs` of the pair whose left value is `left`.
+  fn find_left<Q>(&self, left: &Q) -> Option<usize>
+  where
+    L: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let pairs = &self.pairs;
+    let slot = self.left.find(self.hash_builder.hash_one(left), |&i| pairs[i].0.borrow() == left)?;
+    Some(*self.left.get(slot))
+  }
+
+  fn find_right<Q>(&self, right: &Q) -> Option<usize>
+  where
+    R: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let pairs = &self.pairs;
+    let slot = self.right.find(self.hash_builder.hash_one(right), |&i| pairs[i].1.borrow() == right)?;
+    Some(*self.right.get(slot))
+  }
+
+  /// Removes the pair at `index` from both tables and from `pairs`. The
+  /// last pair takes its place, so its indices are updated.
+  fn remove_at(&mut self, index: usize) -> (L, R) {
+    let (left_hash, right_hash) = self.hashes(index);
+    let slot = self.left.find(left_hash, |&i| i == index).unwrap();
+    self.left.remove(slot);
+    let slot = self.right.find(right_hash, |&i| i == index).unwrap();
+    self.right.remove(slot);
+
+    let pair = self.pairs.swap_remove(index);
+    let moved = self.pairs.len();
+    if index < moved {
+      let (left_hash, right_hash) = self.hashes(index);
+      let slot = self.left.find(left_hash, |&i| i == moved).unwrap();
+      *self.left.get_mut(slot) = index;
+      let slot = self.right.find(right_hash, |&i| i == moved).unwrap();
+      *self.right.get_mut(slot) = index;
+    }
+    pair
+  }
+
+  fn hashes(&self, index: usize) -> (u64, u64) {
+    let (left, right) = &self.pairs[index];
+    (self.hash_builder.hash_one(left), self.hash_builder.hash_one(right))
+  }
+
+  /// Pairs `left` with `right`, first removing any pair which has either
+  /// of them, and reports what was removed.
+  pub fn insert(&mut self, left: L, right: R) -> Overwritten<L, R> {
+    let overwritten = match (self.find_left(&left), self.find_right(&right)) {
+      (None, None) => Overwritten::Neither,
+      (Some(i), Some(j)) if i == j => {
+        let (l, r) = self.remove_at(i);
+        Overwritten::Pair(l, r)
+      }
+      (Some(i), None) => {
+        let (l, r) = self.remove_at(i);
+        Overwritten::Left(l, r)
+      }
+      (None, Some(j)) => {
+        let (l, r) = self.remove_at(j);
+        Overwritten::Right(l, r)
+      }
+      (Some(i), Some(j)) => {
+        // Removing the later pair first leaves the index of the other one
+        // alone.
+        if i > j {
+          let by_left = self.remove_at(i);
+          Overwritten::Both(by_left, self.remove_at(j))
+        } else {
+          let by_right = self.remove_at(j);
+          Overwritten::Both(self.remove_at(i), by_right)
+        }
+      }
+    };
+
+    let pairs = &self.pairs;
+    let hash_builder = &self.hash_builder;
+    self.left.reserve(1, |&i| hash_builder.hash_one(&pairs[i].0));
+    self.right.reserve(1, |&i| hash_builder.hash_one(&pairs[i].1));
+    let index = self.pairs.len();
+    self.left.insert_no_grow(self.hash_builder.hash_one(&left), index);
+    self.right.insert_no_grow(self.hash_builder.hash_one(&right), index);
+    self.pairs.push((left, right));
+    overwritten
+  }
+
+  /// Returns the right value paired with `left`.
+  pub fn get_by_left<Q>(&self, left: &Q) -> Option<&R>
+  where
+    L: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let index = self.find_left(left)?;
+    Some(&self.pairs[index].1)
+  }
+
+  /// Returns the left value paired with `right`.
+  pub fn get_by_right<Q>(&self, right: &Q) -> Option<&L>
+  where
+    R: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let index = self.find_right(right)?;
+    Some(&self.pairs[index].0)
+  }
+
+  pub fn contains_left<Q>(&self, left: &Q) -> bool
+  where
+    L: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    self.find_left(left).is_some()
+  }
+
+  pub fn contains_right<Q>(&self, right: &Q) -> bool
+  where
+    R: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    self.find_right(right).is_some()
+  }
+
+  /// Removes the pair whose left value is `left`, and returns it.
+  pub fn remove_by_left<Q>(&mut self, left: &Q) -> Option<(L, R)>
+  where
+    L: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let index = self.find_left(left)?;
+    Some(self.remove_at(index))
+  }
+
+  /// Removes the pair whose right value is `right`, and returns it.
+  pub fn remove_by_right<Q>(&mut self, right: &Q) -> Option<(L, R)>
+  where
+    R: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let index = self.find_right(right)?;
+    Some(self.remove_at(index))
+  }
+}
+
+
+impl<L, R, S: Default> Default for BiHashMap<L, R, S> {
+    fn default() -> Self {
+        BiHashMap::with_hasher(S::default())
+    }
+}
+
+
+impl<L, R, S> Clone for BiHashMap<L, R, S>
+where
+    L: Clone,
+    R: Clone,
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        BiHashMap {
+            pairs: self.pairs.clone(),
+            left: self.left.clone(),
+            right: self.right.clone(),
+            hash_builder: self.hash_builder.clone(),
+        }
+    }
+}
+
+
+impl<L, R, S> fmt::Debug for BiHashMap<L, R, S>
+where
+    L: fmt::Debug,
+    R: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+
+pub struct Iter<'a, L: 'a, R: 'a> {
+    inner: slice::Iter<'a, (L, R)>,
+}
+
+
+impl<'a, L, R> Iterator for Iter<'a, L, R> {
+    type Item = (&'a L, &'a R);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(l, r)| (l, r))
+    }
+}
+
+
+impl<'a, L, R, S> IntoIterator for &'a BiHashMap<L, R, S> {
+    type Item = (&'a L, &'a R);
+    type IntoIter = Iter<'a, L, R>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+
+impl<L, R, S> FromIterator<(L, R)> for BiHashMap<L, R, S>
+where
+    L: Hash + Eq,
+    R: Hash + Eq,
+    S: BuildHasher + Default,
+{
+    fn from_iter<I: IntoIterator<Item = (L, R)>>(iter: I) -> Self {
+        let mut map = BiHashMap::with_hasher(S::default());
+        map.extend(iter);
+        map
+    }
+}
+
+
+impl<L, R, S> Extend<(L, R)> for BiHashMap<L, R, S>
+where
+    L: Hash + Eq,
+    R: Hash + Eq,
+    S: BuildHasher,
+{
+    fn extend<I: IntoIterator<Item = (L, R)>>(&mut self, iter: I) {
+        for (left, right) in iter {
+            self.insert(left, right);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rng::Rng;
+
+    /// Checks that both tables point at every pair exactly once.
+    fn assert_consistent(map: &BiHashMap<u32, u32>) {
+        assert_eq!(map.left.len(), map.len());
+        assert_eq!(map.right.len(), map.len());
+        for (index, (l, r)) in map.pairs.iter().enumerate() {
+            assert_eq!(map.find_left(l), Some(index));
+            assert_eq!(map.find_right(r), Some(index));
+        }
+    }
+
+    #[test]
+    fn insert() {
+        let mut map = BiHashMap::new();
+        assert_eq!(map.insert(1, "a"), Overwritten::Neither);
+        assert_eq!(map.insert(2, "b"), Overwritten::Neither);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get_by_left(&1), Some(&"a"));
+        assert_eq!(map.get_by_right(&"b"), Some(&2));
+    }
+
+    #[test]
+    fn insert_overwrites() {
+        let mut map = BiHashMap::new();
+        map.insert(1, 10);
+        map.insert(2, 20);
+        map.insert(3, 30);
+        assert_eq!(map.insert(1, 10), Overwritten::Pair(1, 10));
+        assert_eq!(map.insert(1, 11), Overwritten::Left(1, 10));
+        assert_eq!(map.insert(4, 20), Overwritten::Right(2, 20));
+        assert_eq!(map.insert(3, 11), Overwritten::Both((3, 30), (1, 11)));
+        assert_consistent(&map);
+
+        let mut pairs: Vec<_> = map.iter().map(|(&l, &r)| (l, r)).collect();
+        pairs.sort();
+        assert_eq!(pairs, [(3, 11), (4, 20)]);
+        assert_eq!(map.get_by_left(&1), None);
+        assert_eq!(map.get_by_right(&30), None);
+    }
+
+    #[test]
+    fn borrowed_lookup() {
+        let mut map = BiHashMap::new();
+        map.insert(String::from("ext-1"), String::from("int-1"));
+        assert_eq!(map.get_by_left("ext-1").map(String::as_str), Some("int-1"));
+        assert_eq!(map.get_by_right("int-1").map(String::as_str), Some("ext-1"));
+        assert!(map.contains_left("ext-1"));
+        assert!(!map.contains_right("ext-1"));
+    }
+
+    #[test]
+    fn remove_by_left() {
+        let mut map: BiHashMap<_, _> = (0..10).map(|i| (i, i * 10)).collect();
+        assert_eq!(map.remove_by_left(&3), Some((3, 30)));
+        assert_eq!(map.remove_by_left(&3), None);
+        assert_eq!(map.get_by_right(&30), None);
+        assert_consistent(&map);
+    }
+
+    #[test]
+    fn remove_by_right() {
+        let mut map: BiHashMap<_, _> = (0..10).map(|i| (i, i * 10)).collect();
+        assert_eq!(map.remove_by_right(&0), Some((0, 0)));
+        assert_eq!(map.remove_by_right(&90), Some((9, 90)));
+        assert_eq!(map.get_by_left(&0), None);
+        assert_eq!(map.len(), 8);
+        assert_consistent(&map);
+    }
+
+    #[test]
+    fn stays_one_to_one() {
+        // Random inserts and removals, checked against a pair of maps kept
+        // in sync by hand.
+        let mut map = BiHashMap::new();
+        let mut by_left = std::collections::HashMap::new();
+        let mut by_right = std::collections::HashMap::new();
+        let mut rng = Rng::new(1);
+        for _ in 0..5000 {
+            let (l, r) = (rng.below(64) as u32, rng.below(64) as u32);
+            if rng.below(4) == 0 {
+                let removed = map.remove_by_left(&l);
+                assert_eq!(removed.map(|(_, r)| r), by_left.remove(&l));
+                if let Some((_, r)) = removed {
+                    by_right.remove(&r);
+                }
+            } else {
+                map.insert(l, r);
+                if let Some(old) = by_left.insert(l, r) {
+                    by_right.remove(&old);
+                }
+                if let Some(old) = by_right.insert(r, l) {
+                    if old != l {
+                        by_left.remove(&old);
+                    }
+                }
+            }
+            assert_eq!(map.len(), by_left.len());
+        }
+        assert_consistent(&map);
+        for (l, r) in &by_left {
+            assert_eq!(map.get_by_left(l), Some(r));
+            assert_eq!(map.get_by_right(r), Some(l));
+        }
+    }
+
+    #[test]
+    fn clear() {
+        let mut map: BiHashMap<_, _> = (0..10).map(|i| (i, i)).collect();
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.get_by_left(&1), None);
+    }
+
+    #[test]
+    fn debug() {
+        let mut map = BiHashMap::new();
+        map.insert(1, "a");
+        assert_eq!(format!("{:?}", map), r#"{1: "a"}"#);
+    }
+}
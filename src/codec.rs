@@ -0,0 +1,608 @@
+//! A compact binary format to save a `HashMap` and load it back.
+//!
+//! A file holds, in order, all integers little-endian:
+//!
+//! | field   | encoding                                              |
+//! |---------|-------------------------------------------------------|
+//! | magic   | the 4 bytes `HMAP`                                    |
+//! | version | `u16`, currently `VERSION`                            |
+//! | count   | `u64`, the number of entries                          |
+//! | hasher  | `u16` length, then the hasher's UTF-8 `HasherId::ID`  |
+//! | payload | `u64` length, then each key followed by its value     |
+//! | crc     | `u32`, the CRC-32 of all the bytes before it          |
+//!
+//! Keys and values encode through the `Encode` and `Decode` traits.
+//! Hashes aren't saved: loading hashes the keys again, so the hasher only
+//! needs to be of the same kind, not seeded the same.
+//!
+//! Version 1 identified the hasher by its `std::any::type_name`, which may
+//! change with the compiler; it is no longer read.
+
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::convert::{TryFrom, TryInto};
+use std::error;
+use std::fmt;
+use std::hash::{BuildHasher, BuildHasherDefault, Hash};
+use std::io::{self, Read, Write};
+use std::mem;
+
+use crate::{Entry, HashMap};
+
+
+pub const MAGIC: [u8; 4] = *b"HMAP";
+pub const VERSION: u16 = 2;
+
+
+/// Why a map couldn't be loaded.
+#[derive(Debug)]
+pub enum Error {
+    /// The reader failed.
+    Io(io::Error),
+    /// The input ended before the map did.
+    Truncated,
+    /// The input doesn't start with `MAGIC`.
+    BadMagic,
+    /// The input is in a version of the format this one doesn't know.
+    UnsupportedVersion(u16),
+    /// The map was saved with another hasher, by `HasherId::ID`.
+    HasherMismatch { expected: String, found: String },
+    /// The input isn't what was saved.
+    Checksum { expected: u32, found: u32 },
+    /// The input passed the checksum, but doesn't decode to a map.
+    Invalid(&'static str),
+}
+
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::Truncated => f.write_str("input ended early"),
+            Error::BadMagic => f.write_str("not a saved map"),
+            Error::UnsupportedVersion(version) => write!(f, "unsupported format version {}", version),
+            Error::HasherMismatch { expected, found } => {
+                write!(f, "map saved with hasher {}, not {}", found, expected)
+            }
+            Error::Checksum { expected, found } => {
+                write!(f, "checksum mismatch: expected {:#010x}, found {:#010x}", expected, found)
+            }
+            Error::Invalid(reason) => write!(f, "invalid map: {}", reason),
+        }
+    }
+}
+
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => Error::Truncated,
+            _ => Error::Io(err),
+        }
+    }
+}
+
+
+/// A hasher which maps can be saved with.
+pub trait HasherId {
+    /// A name for the hasher, saved with maps and checked when they are
+    /// loaded. It must never change once maps have been saved with it, and
+    /// must differ from that of any hasher hashing keys differently.
+    const ID: &'static str;
+}
+
+
+impl HasherId for RandomState {
+    const ID: &'static str = "std::RandomState";
+}
+
+
+impl HasherId for BuildHasherDefault<DefaultHasher> {
+    const ID: &'static str = "std::DefaultHasher";
+}
+
+
+/// A type which can be saved in a map.
+pub trait Encode {
+    /// Appends the encoding of `self` to `out`.
+    fn encode(&self, out: &mut Vec<u8>);
+}
+
+
+/// A type which can be loaded from a map, as encoded by its `Encode`.
+pub trait Decode: Sized {
+    /// Decodes a value from the start of `input`, and advances `input` past
+    /// it.
+    fn decode(input: &mut &[u8]) -> Result<Self, Error>;
+}
+
+
+/// Splits off the first `n` bytes of `input`.
+fn take<'a>(input: &mut &'a [u8], n: usize) -> Result<&'a [u8], Error> {
+    if input.len() < n {
+        return Err(Error::Truncated);
+    }
+    let (head, tail) = input.split_at(n);
+    *input = tail;
+    Ok(head)
+}
+
+
+macro_rules! impl_int {
+    ($($t:ty),*) => {
+        $(
+            impl Encode for $t {
+                fn encode(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+            }
+
+            impl Decode for $t {
+                fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+                    let bytes = take(input, mem::size_of::<$t>())?;
+                    Ok(<$t>::from_le_bytes(bytes.try_into().unwrap()))
+                }
+            }
+        )*
+    };
+}
+
+
+impl_int!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+
+/// As a `u64`, to be the same on every platform.
+impl Encode for usize {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u64).encode(out);
+    }
+}
+
+
+impl Decode for usize {
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        usize::try_from(u64::decode(input)?).map_err(|_| Error::Invalid("length out of range"))
+    }
+}
+
+
+impl Encode for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+}
+
+
+impl Decode for bool {
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        match u8::decode(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::Invalid("invalid bool")),
+        }
+    }
+}
+
+
+impl Encode for char {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u32).encode(out);
+    }
+}
+
+
+impl Decode for char {
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        char::from_u32(u32::decode(input)?).ok_or(Error::Invalid("invalid char"))
+    }
+}
+
+
+/// As its length in bytes, then its UTF-8 bytes.
+impl Encode for str {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.len().encode(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+
+impl Encode for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_str().encode(out);
+    }
+}
+
+
+impl Decode for String {
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        let len = usize::decode(input)?;
+        let bytes = take(input, len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| Error::Invalid("invalid UTF-8"))
+    }
+}
+
+
+/// As its length, then its elements.
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.len().encode(out);
+        for item in self {
+            item.encode(out);
+        }
+    }
+}
+
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_slice().encode(out);
+    }
+}
+
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        let len = usize::decode(input)?;
+        // Don't trust `len` with more memory than the input could fill.
+        let mut items = Vec::with_capacity(len.min(input.len()));
+        for _ in 0..len {
+            items.push(T::decode(input)?);
+        }
+        Ok(items)
+    }
+}
+
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            None => out.push(0),
+            Some(value) => {
+                out.push(1);
+                value.encode(out);
+            }
+        }
+    }
+}
+
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        match u8::decode(input)? {
+            0 => Ok(None),
+            1 => T::decode(input).map(Some),
+            _ => Err(Error::Invalid("invalid option")),
+        }
+    }
+}
+
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+    }
+}
+
+
+impl<A: Decode, B: Decode> Decode for (A, B) {
+    fn decode(input: &mut &[u8]) -> Result<Self, Error> {
+        Ok((A::decode(input)?, B::decode(input)?))
+    }
+}
+
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (**self).encode(out);
+    }
+}
+
+
+/// The CRC-32 lookup table, for the reflected IEEE polynomial.
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { 0xEDB8_8320 ^ (crc >> 1) } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &byte| CRC_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+
+/// Reads exactly `len` bytes, without trusting `len` with memory before
+/// they arrive.
+fn read_exact_vec<R: Read>(reader: &mut R, len: u64) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::new();
+    reader.take(len).read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < len {
+        return Err(Error::Truncated);
+    }
+    Ok(bytes)
+}
+
+
+impl<K, V, S> HashMap<K, V, S>
+where
+  K: Hash + Eq + Encode,
+  V: Encode,
+  S: BuildHasher + HasherId,
+{
+  /// Saves the map to `writer`, in the format of the `codec` module.
+  pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+    let hasher = S::ID;
+    let hasher_len = u16::try_from(hasher.len())
+      .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "hasher id too long"))?;
+    let mut payload = Vec::new();
+    for (key, value) in self {
+      key.encode(&mut payload);
+      value.encode(&mut payload);
+    }
+
+    let mut out = Vec::with_capacity(payload.len() + hasher.len() + 32);
+    out.extend_from_slice(&MAGIC);
+    VERSION.encode(&mut out);
+    (self.len() as u64).encode(&mut out);
+    hasher_len.encode(&mut out);
+    out.extend_from_slice(hasher.as_bytes());
+    (payload.len() as u64).encode(&mut out);
+    out.extend_from_slice(&payload);
+    crc32(&out).encode(&mut out);
+    writer.write_all(&out)
+  }
+}
+
+
+impl<K, V, S> HashMap<K, V, S>
+where
+  K: Hash + Eq + Decode,
+  V: Decode,
+  S: BuildHasher + HasherId + Default,
+{
+  /// Loads a map saved by `write_to`, with a hasher of the same `ID`.
+  ///
+  /// The whole input is checked before any entry is decoded, and the table
+  /// is sized once for all of them.
+  pub fn read_from<R: Read>(mut reader: R) -> Result<Self, Error> {
+    let mut header = [0; 4 + 2 + 8 + 2];
+    reader.read_exact(&mut header)?;
+    let mut input = &header[..];
+    if take(&mut input, 4)? != MAGIC {
+      return Err(Error::BadMagic);
+    }
+    let version = u16::decode(&mut input)?;
+    if version != VERSION {
+      return Err(Error::UnsupportedVersion(version));
+    }
+    let count = u64::decode(&mut input)?;
+    let hasher_len = u16::decode(&mut input)?;
+
+    let hasher = read_exact_vec(&mut reader, hasher_len.into())?;
+    let mut payload_len = [0; 8];
+    reader.read_exact(&mut payload_len)?;
+    let payload = read_exact_vec(&mut reader, u64::from_le_bytes(payload_len))?;
+    let mut crc = [0; 4];
+    reader.read_exact(&mut crc)?;
+
+    let mut digest = Vec::with_capacity(header.len() + hasher.len() + 8 + payload.len());
+    digest.extend_from_slice(&header);
+    digest.extend_from_slice(&hasher);
+    digest.extend_from_slice(&payload_len);
+    digest.extend_from_slice(&payload);
+    let (expected, found) = (crc32(&digest), u32::from_le_bytes(crc));
+    if expected != found {
+      return Err(Error::Checksum { expected, found });
+    }
+
+    let expected = S::ID;
+    if hasher != expected.as_bytes() {
+      let found = String::from_utf8_lossy(&hasher).into_owned();
+      return Err(Error::HasherMismatch { expected: expected.to_owned(), found });
+    }
+
+    // Each entry takes a byte at least, but for empty types: don't trust
+    // `count` with more memory than the payload could fill.
+    let count = usize::try_from(count).map_err(|_| Error::Invalid("count out of range"))?;
+    let mut map = HashMap::with_capacity_and_hasher(count.min(payload.len()), S::default());
+    let mut input = &payload[..];
+    for _ in 0..count {
+      let key = K::decode(&mut input)?;
+      let value = V::decode(&mut input)?;
+      match map.entry(key) {
+        Entry::Occupied(_) => return Err(Error::Invalid("duplicate key")),
+        Entry::Vacant(entry) => {
+          entry.insert(value);
+        }
+      }
+    }
+    if !input.is_empty() {
+      return Err(Error::Invalid("trailing bytes after the entries"));
+    }
+    Ok(map)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::hash::Hasher;
+
+    fn saved<K, V, S>(map: &HashMap<K, V, S>) -> Vec<u8>
+    where
+        K: Hash + Eq + Encode,
+        V: Encode,
+        S: BuildHasher + HasherId,
+    {
+        let mut bytes = Vec::new();
+        map.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn crc32() {
+        assert_eq!(super::crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(super::crc32(b""), 0);
+    }
+
+    #[test]
+    fn round_trip() {
+        let map: HashMap<String, Vec<u32>> = (0..100).map(|i| (i.to_string(), (0..i % 5).collect())).collect();
+        let loaded = HashMap::<String, Vec<u32>>::read_from(&saved(&map)[..]).unwrap();
+        assert_eq!(loaded, map);
+
+        let map: HashMap<(u8, char), Option<bool>> =
+            vec![((1, 'a'), Some(true)), ((2, 'é'), None)].into_iter().collect();
+        let loaded = HashMap::read_from(&saved(&map)[..]).unwrap();
+        assert_eq!(map, loaded);
+    }
+
+    #[test]
+    fn empty() {
+        let map = HashMap::<u32, u32>::new();
+        let loaded = HashMap::<u32, u32>::read_from(&saved(&map)[..]).unwrap();
+        assert!(loaded.is_empty());
+    }
+
+    #[test]
+    fn header() {
+        let map: HashMap<u32, u32> = vec![(1, 2)].into_iter().collect();
+        let bytes = saved(&map);
+        assert_eq!(bytes[..4], MAGIC);
+        assert_eq!(bytes[4..6], VERSION.to_le_bytes());
+        assert_eq!(bytes[6..14], 1u64.to_le_bytes());
+        assert_eq!(bytes[14..16], 16u16.to_le_bytes());
+        assert_eq!(&bytes[16..32], b"std::RandomState");
+        let crc = u32::from_le_bytes(bytes[bytes.len() - 4..].try_into().unwrap());
+        assert_eq!(crc, super::crc32(&bytes[..bytes.len() - 4]));
+    }
+
+    #[test]
+    fn sized_once() {
+        let map: HashMap<u32, u32> = (0..1000).map(|i| (i, i)).collect();
+        let loaded = HashMap::<u32, u32>::read_from(&saved(&map)[..]).unwrap();
+        assert_eq!(loaded.capacity(), HashMap::<u32, u32>::with_capacity(1000).capacity());
+    }
+
+    #[test]
+    fn truncated() {
+        let map: HashMap<u32, String> = (0..10).map(|i| (i, i.to_string())).collect();
+        let bytes = saved(&map);
+        for len in 0..bytes.len() {
+            match HashMap::<u32, String>::read_from(&bytes[..len]) {
+                Err(Error::Truncated) => {}
+                other => panic!("{} bytes: {:?}", len, other.map(|m| m.len())),
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt() {
+        let map: HashMap<u32, String> = (0..10).map(|i| (i, i.to_string())).collect();
+        let bytes = saved(&map);
+        for i in 0..bytes.len() {
+            let mut corrupt = bytes.clone();
+            corrupt[i] ^= 0x40;
+            assert!(HashMap::<u32, String>::read_from(&corrupt[..]).is_err());
+        }
+
+        let mut corrupt = bytes.clone();
+        corrupt[0] = b'X';
+        assert!(matches!(HashMap::<u32, String>::read_from(&corrupt[..]), Err(Error::BadMagic)));
+        let mut corrupt = bytes.clone();
+        corrupt[4] = 9;
+        assert!(matches!(HashMap::<u32, String>::read_from(&corrupt[..]), Err(Error::UnsupportedVersion(9))));
+        let mut corrupt = bytes;
+        let last = corrupt.len() - 5;
+        corrupt[last] ^= 1;
+        assert!(matches!(HashMap::<u32, String>::read_from(&corrupt[..]), Err(Error::Checksum { .. })));
+    }
+
+    #[test]
+    fn hasher_mismatch() {
+        let map: HashMap<u32, u32> = vec![(1, 2)].into_iter().collect();
+        let loaded = HashMap::<u32, u32, BuildHasherDefault<DefaultHasher>>::read_from(&saved(&map)[..]);
+        assert!(matches!(loaded, Err(Error::HasherMismatch { .. })));
+    }
+
+    #[test]
+    fn custom_hasher() {
+        #[derive(Default)]
+        struct Fnv(u64);
+
+        impl Hasher for Fnv {
+            fn finish(&self) -> u64 {
+                self.0
+            }
+
+            fn write(&mut self, bytes: &[u8]) {
+                for &byte in bytes {
+                    self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100_0000_01B3);
+                }
+            }
+        }
+
+        impl HasherId for BuildHasherDefault<Fnv> {
+            const ID: &'static str = "fnv-1a";
+        }
+
+        type FnvMap<K, V> = HashMap<K, V, BuildHasherDefault<Fnv>>;
+        let map: FnvMap<u32, u32> = (0..10).map(|i| (i, i)).collect();
+        let bytes = saved(&map);
+        assert_eq!(FnvMap::<u32, u32>::read_from(&bytes[..]).unwrap(), map);
+        match HashMap::<u32, u32>::read_from(&bytes[..]) {
+            Err(Error::HasherMismatch { expected, found }) => {
+                assert_eq!((&*expected, &*found), ("std::RandomState", "fnv-1a"));
+            }
+            other => panic!("{:?}", other.map(|m| m.len())),
+        }
+    }
+
+    #[test]
+    fn invalid_payload() {
+        // A well-formed file whose payload holds the same key twice.
+        let mut payload = Vec::new();
+        for _ in 0..2 {
+            1u32.encode(&mut payload);
+            2u32.encode(&mut payload);
+        }
+        let hasher = RandomState::ID;
+        let mut bytes = MAGIC.to_vec();
+        VERSION.encode(&mut bytes);
+        2u64.encode(&mut bytes);
+        (hasher.len() as u16).encode(&mut bytes);
+        bytes.extend_from_slice(hasher.as_bytes());
+        payload.encode(&mut bytes);
+        let crc = super::crc32(&bytes);
+        crc.encode(&mut bytes);
+        assert!(matches!(HashMap::<u32, u32>::read_from(&bytes[..]), Err(Error::Invalid("duplicate key"))));
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Error::Truncated.to_string(), "input ended early");
+        assert_eq!(Error::UnsupportedVersion(9).to_string(), "unsupported format version 9");
+    }
+}
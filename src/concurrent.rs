@@ -0,0 +1,525 @@
+//! A hash map which many threads can use at once.
+
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::thread;
+
+use crate::raw::{self, RawTable};
+
+
+type Shard<K, V> = RwLock<RawTable<(K, V)>>;
+
+
+/// A hash map split into shards, each its own table behind its own
+/// `RwLock`, so that threads working on keys in different shards don't
+/// wait for each other.
+///
+/// Every method takes `&self`. Each key is hashed once with the
+/// `BuildHasher` `S`: some bits of the hash pick the shard, and the
+/// table of that shard uses the hash like `HashMap` does.
+///
+/// `get`, `get_mut` and `entry` return guards which keep their shard
+/// locked until dropped. Calling another method which needs the same
+/// shard from the thread holding such a guard deadlocks.
+pub struct ConcurrentHashMap<K, V, S = RandomState> {
+    shards: Box<[Shard<K, V>]>,
+    /// `64 - log2(shards.len())`, to pick a shard from a hash.
+    shift: u32,
+    hash_builder: S,
+}
+
+
+/// Number of shards used by default: a few per CPU, so that threads rarely
+/// collide.
+fn default_shard_count() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get()) * 4
+}
+
+
+impl<K, V> ConcurrentHashMap<K, V, RandomState> {
+    pub fn new() -> Self {
+        ConcurrentHashMap::with_shards_and_hasher(default_shard_count(), RandomState::new())
+    }
+
+    /// Creates an empty map with `shards` shards, rounded up to a power of
+    /// two.
+    pub fn with_shards(shards: usize) -> Self {
+        ConcurrentHashMap::with_shards_and_hasher(shards, RandomState::new())
+    }
+}
+
+
+impl<K, V, S> ConcurrentHashMap<K, V, S> {
+    /// Creates an empty map which will use `hash_builder` to hash keys.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        ConcurrentHashMap::with_shards_and_hasher(default_shard_count(), hash_builder)
+    }
+
+    /// Creates an empty map with `shards` shards, rounded up to a power of
+    /// two, which will use `hash_builder` to hash keys.
+    pub fn with_shards_and_hasher(shards: usize, hash_builder: S) -> Self {
+        let shards = shards.max(1).next_power_of_two();
+        ConcurrentHashMap {
+            shards: (0..shards).map(|_| RwLock::new(RawTable::new())).collect(),
+            shift: 64 - shards.trailing_zeros(),
+            hash_builder,
+        }
+    }
+
+    /// Returns a reference to the map's `BuildHasher`.
+    pub fn hasher(&self) -> &S {
+        &self.hash_builder
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Number of entries. Other threads may change it right away.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| read(shard).len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| read(shard).len() == 0)
+    }
+
+    /// Removes every entry, one shard at a time.
+    pub fn clear(&self) {
+        for shard in self.shards.iter() {
+            write(shard).clear();
+        }
+    }
+
+    /// Keeps only the entries for which `f` returns `true`, locking one
+    /// shard at a time.
+    pub fn retain<F>(&self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        for shard in self.shards.iter() {
+            let mut table = write(shard);
+            let mut sweep = table.sweep();
+            while sweep.next_if(|(k, v)| !f(k, v)).is_some() {}
+        }
+    }
+}
+
+
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().expect("shard lock poisoned")
+}
+
+
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().expect("shard lock poisoned")
+}
+
+
+impl<K, V, S> ConcurrentHashMap<K, V, S>
+where
+  K: Hash + Eq,
+  S: BuildHasher,
+{
+  /// Returns the hash of `key` and the shard it goes in.
+  fn shard<Q: Hash + ?Sized>(&self, key: &Q) -> (u64, &Shard<K, V>) {
+    let hash = self.hash_builder.hash_one(key);
+    // The table takes its bits from the top of the hash times the same
+    // constant, so rotate it first to pick the shard from other bits.
+    let mixed = hash.rotate_left(32).wrapping_mul(raw::FIBONACCI);
+    let index = mixed.checked_shr(self.shift).unwrap_or(0) as usize;
+    (hash, &self.shards[index])
+  }
+
+  pub fn insert(&self, key: K, value: V) -> Option<V> {
+    match self.entry(key) {
+      Entry::Occupied(mut entry) => Some(entry.insert(value)),
+      Entry::Vacant(entry) => {
+        entry.insert(value);
+        None
+      }
+    }
+  }
+
+  /// Gets the given key's entry for in-place manipulation. Its shard stays
+  /// locked for writing until the entry, or the guard it turns into, is
+  /// dropped.
+  pub fn entry(&self, key: K) -> Entry<'_, K, V> {
+    let (hash, shard) = self.shard(&key);
+    let mut table = write(shard);
+    if let Some(index) = table.find(hash, |(k, _)| *k == key) {
+      return Entry::Occupied(OccupiedEntry { table, index });
+    }
+    // Only make room for keys which aren't there yet.
+    let hash_builder = &self.hash_builder;
+    table.reserve(1, |(k, _)| hash_builder.hash_one(k));
+    Entry::Vacant(VacantEntry { table, hash, key })
+  }
+
+  /// Returns a guard through which the value of `key` can be read. Its
+  /// shard stays locked for reading until the guard is dropped.
+  pub fn get<Q>(&self, key: &Q) -> Option<Ref<'_, K, V>>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let (hash, shard) = self.shard(key);
+    let table = read(shard);
+    let index = table.find(hash, |(k, _)| k.borrow() == key)?;
+    Some(Ref { table, index })
+  }
+
+  /// Returns a guard through which the value of `key` can be modified. Its
+  /// shard stays locked for writing until the guard is dropped.
+  pub fn get_mut<Q>(&self, key: &Q) -> Option<RefMut<'_, K, V>>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let (hash, shard) = self.shard(key);
+    let table = write(shard);
+    let index = table.find(hash, |(k, _)| k.borrow() == key)?;
+    Some(RefMut { table, index })
+  }
+
+  pub fn contains_key<Q>(&self, key: &Q) -> bool
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let (hash, shard) = self.shard(key);
+    read(shard).find(hash, |(k, _)| k.borrow() == key).is_some()
+  }
+
+  pub fn remove<Q>(&self, key: &Q) -> Option<V>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let (hash, shard) = self.shard(key);
+    let mut table = write(shard);
+    let index = table.find(hash, |(k, _)| k.borrow() == key)?;
+    Some(table.remove(index).1)
+  }
+}
+
+
+impl<K, V> Default for ConcurrentHashMap<K, V, RandomState> {
+    fn default() -> Self {
+        ConcurrentHashMap::new()
+    }
+}
+
+
+impl<K, V, S> fmt::Debug for ConcurrentHashMap<K, V, S>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut map = f.debug_map();
+        for shard in self.shards.iter() {
+            map.entries(read(shard).iter().map(|(k, v)| (k, v)));
+        }
+        map.finish()
+    }
+}
+
+
+/// A read-locked entry of a `ConcurrentHashMap`, which derefs to its value.
+pub struct Ref<'a, K: 'a, V: 'a> {
+    table: RwLockReadGuard<'a, RawTable<(K, V)>>,
+    index: usize,
+}
+
+
+impl<'a, K, V> Ref<'a, K, V> {
+    pub fn key(&self) -> &K {
+        &self.table.get(self.index).0
+    }
+
+    pub fn value(&self) -> &V {
+        &self.table.get(self.index).1
+    }
+}
+
+
+impl<'a, K, V> Deref for Ref<'a, K, V> {
+    type Target = V;
+    fn deref(&self) -> &V {
+        self.value()
+    }
+}
+
+
+/// A write-locked entry of a `ConcurrentHashMap`, which derefs to its
+/// value.
+pub struct RefMut<'a, K: 'a, V: 'a> {
+    table: RwLockWriteGuard<'a, RawTable<(K, V)>>,
+    index: usize,
+}
+
+
+impl<'a, K, V> RefMut<'a, K, V> {
+    pub fn key(&self) -> &K {
+        &self.table.get(self.index).0
+    }
+
+    pub fn value(&self) -> &V {
+        &self.table.get(self.index).1
+    }
+
+    pub fn value_mut(&mut self) -> &mut V {
+        &mut self.table.get_mut(self.index).1
+    }
+}
+
+
+impl<'a, K, V> Deref for RefMut<'a, K, V> {
+    type Target = V;
+    fn deref(&self) -> &V {
+        self.value()
+    }
+}
+
+
+impl<'a, K, V> DerefMut for RefMut<'a, K, V> {
+    fn deref_mut(&mut self) -> &mut V {
+        self.value_mut()
+    }
+}
+
+
+pub enum Entry<'a, K: 'a, V: 'a> {
+  Occupied(OccupiedEntry<'a, K, V>),
+  Vacant(VacantEntry<'a, K, V>),
+}
+
+
+pub struct OccupiedEntry<'a, K: 'a, V: 'a> {
+  table: RwLockWriteGuard<'a, RawTable<(K, V)>>,
+  index: usize,
+}
+
+
+pub struct VacantEntry<'a, K: 'a, V: 'a> {
+  table: RwLockWriteGuard<'a, RawTable<(K, V)>>,
+  hash: u64,
+  key: K,
+}
+
+
+impl<'a, K, V> Entry<'a, K, V> {
+  pub fn or_insert(self, default: V) -> RefMut<'a, K, V> {
+    match self {
+      Entry::Occupied(entry) => entry.into_ref(),
+      Entry::Vacant(entry) => entry.insert(default),
+    }
+  }
+
+  pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> RefMut<'a, K, V> {
+    match self {
+      Entry::Occupied(entry) => entry.into_ref(),
+      Entry::Vacant(entry) => entry.insert(default()),
+    }
+  }
+
+  pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+    match self {
+      Entry::Occupied(mut entry) => {
+        f(entry.get_mut());
+        Entry::Occupied(entry)
+      }
+      Entry::Vacant(entry) => Entry::Vacant(entry),
+    }
+  }
+
+  pub fn key(&self) -> &K {
+    match self {
+      Entry::Occupied(entry) => entry.key(),
+      Entry::Vacant(entry) => entry.key(),
+    }
+  }
+}
+
+
+impl<'a, K, V: Default> Entry<'a, K, V> {
+  pub fn or_default(self) -> RefMut<'a, K, V> {
+    self.or_insert_with(V::default)
+  }
+}
+
+
+impl<'a, K, V> OccupiedEntry<'a, K, V> {
+  pub fn key(&self) -> &K {
+    &self.table.get(self.index).0
+  }
+
+  pub fn get(&self) -> &V {
+    &self.table.get(self.index).1
+  }
+
+  pub fn get_mut(&mut self) -> &mut V {
+    &mut self.table.get_mut(self.index).1
+  }
+
+  /// Turns the entry into a guard on its value.
+  pub fn into_ref(self) -> RefMut<'a, K, V> {
+    RefMut { table: self.table, index: self.index }
+  }
+
+  pub fn insert(&mut self, value: V) -> V {
+    mem::replace(self.get_mut(), value)
+  }
+
+  pub fn remove(mut self) -> V {
+    self.table.remove(self.index).1
+  }
+}
+
+
+impl<'a, K, V> VacantEntry<'a, K, V> {
+  pub fn key(&self) -> &K {
+    &self.key
+  }
+
+  pub fn insert(mut self, value: V) -> RefMut<'a, K, V> {
+    let index = self.table.insert_no_grow(self.hash, (self.key, value));
+    RefMut { table: self.table, index }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert() {
+        let map = ConcurrentHashMap::new();
+        assert_eq!(map.insert(1, "a"), None);
+        assert_eq!(map.insert(1, "b"), Some("a"));
+        assert_eq!(map.len(), 1);
+        assert_eq!(*map.get(&1).unwrap(), "b");
+    }
+
+    #[test]
+    fn get() {
+        let map = ConcurrentHashMap::with_shards(4);
+        map.insert(String::from("foo"), 1);
+        let value = map.get("foo").unwrap();
+        assert_eq!(value.key(), "foo");
+        assert_eq!(*value, 1);
+        assert!(map.get("bar").is_none());
+    }
+
+    #[test]
+    fn get_mut() {
+        let map = ConcurrentHashMap::new();
+        map.insert(1, 1);
+        *map.get_mut(&1).unwrap() += 1;
+        assert_eq!(*map.get(&1).unwrap(), 2);
+    }
+
+    #[test]
+    fn remove() {
+        let map = ConcurrentHashMap::new();
+        map.insert(1, 1);
+        assert_eq!(map.remove(&1), Some(1));
+        assert_eq!(map.remove(&1), None);
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn entry() {
+        let map = ConcurrentHashMap::new();
+        *map.entry("a").or_insert(0) += 1;
+        *map.entry("a").or_insert(0) += 1;
+        map.entry("b").and_modify(|v| *v += 10).or_default();
+        map.entry("a").and_modify(|v| *v += 10).or_default();
+        assert_eq!(*map.get("a").unwrap(), 12);
+        assert_eq!(*map.get("b").unwrap(), 0);
+        if let Entry::Occupied(entry) = map.entry("b") {
+            assert_eq!(entry.remove(), 0);
+        }
+        assert!(!map.contains_key("b"));
+    }
+
+    #[test]
+    fn entry_occupied_never_resizes() {
+        let map = ConcurrentHashMap::with_shards(1);
+        map.insert(0, 0);
+        let capacity = read(&map.shards[0]).capacity();
+        for i in 1..capacity {
+            map.insert(i, i);
+        }
+        for i in 0..capacity {
+            map.insert(i, i + 1);
+        }
+        assert_eq!(read(&map.shards[0]).capacity(), capacity);
+    }
+
+    #[test]
+    fn retain() {
+        let map = ConcurrentHashMap::new();
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        map.retain(|&k, v| {
+            *v *= 2;
+            k % 2 == 0
+        });
+        assert_eq!(map.len(), 50);
+        assert_eq!(*map.get(&10).unwrap(), 20);
+        assert!(map.get(&11).is_none());
+    }
+
+    #[test]
+    fn shard_count() {
+        assert_eq!(ConcurrentHashMap::<i32, i32>::with_shards(5).shard_count(), 8);
+        assert_eq!(ConcurrentHashMap::<i32, i32>::with_shards(0).shard_count(), 1);
+
+        let map = ConcurrentHashMap::with_shards(1);
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.len(), 100);
+    }
+
+    #[test]
+    fn spreads_over_shards() {
+        let map = ConcurrentHashMap::with_shards(16);
+        for i in 0..1600 {
+            map.insert(i, i);
+        }
+        for shard in map.shards.iter() {
+            assert!(read(shard).len() > 50);
+        }
+    }
+
+    #[test]
+    fn threads() {
+        let map = ConcurrentHashMap::with_shards(8);
+        thread::scope(|scope| {
+            for t in 0..8 {
+                let map = &map;
+                scope.spawn(move || {
+                    for i in 0..1000 {
+                        map.insert(t * 1000 + i, i);
+                        *map.entry(-1).or_insert(0) += 1;
+                    }
+                });
+            }
+        });
+        assert_eq!(map.len(), 8001);
+        assert_eq!(*map.get(&-1).unwrap(), 8000);
+        for key in 0..8000 {
+            assert_eq!(*map.get(&key).unwrap(), key % 1000);
+        }
+    }
+}
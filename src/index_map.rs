@@ -0,0 +1,616 @@
+//! A hash map which remembers the order entries were inserted in.
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::iter::FromIterator;
+use std::mem;
+use std::ops::Index;
+use std::slice;
+use std::vec;
+
+use crate::raw::RawTable;
+
+
+/// An entry of an `IndexMap`, with the hash of its key so that the index
+/// never has to hash keys again.
+#[derive(Clone)]
+struct Bucket<K, V> {
+    hash: u64,
+    key: K,
+    value: V,
+}
+
+
+/// A hash map which keeps its entries in a vector, in insertion order
+/// unless moved or sorted, and finds them through a table of positions
+/// hashed by key.
+///
+/// Iteration follows that order, so it doesn't change when the table
+/// grows, and entries can also be reached by position. Updating the value
+/// of a key keeps its position.
+pub struct IndexMap<K, V, S = RandomState> {
+    entries: Vec<Bucket<K, V>>,
+    /// Positions in `entries`, hashed by key.
+    indices: RawTable<usize>,
+    hash_builder: S,
+}
+
+
+impl<K, V> IndexMap<K, V, RandomState> {
+    pub fn new() -> Self {
+        IndexMap::with_hasher(RandomState::new())
+    }
+
+    /// Creates an empty map able to hold `capacity` entries without
+    /// resizing.
+    pub fn with_capacity(capacity: usize) -> Self {
+        IndexMap::with_capacity_and_hasher(capacity, RandomState::new())
+    }
+}
+
+
+impl<K, V, S> IndexMap<K, V, S> {
+    /// Creates an empty map which will use `hash_builder` to hash keys.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        IndexMap {
+            entries: Vec::new(),
+            indices: RawTable::new(),
+            hash_builder,
+        }
+    }
+
+    /// Creates an empty map able to hold `capacity` entries without
+    /// resizing, which will use `hash_builder` to hash keys.
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
+        IndexMap {
+            entries: Vec::with_capacity(capacity),
+            indices: RawTable::with_capacity(capacity),
+            hash_builder,
+        }
+    }
+
+    /// Returns a reference to the map's `BuildHasher`.
+    pub fn hasher(&self) -> &S {
+        &self.hash_builder
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Visits the entries in order.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { inner: self.entries.iter() }
+    }
+
+    /// Visits the entries in order, with mutable values.
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut { inner: self.entries.iter_mut() }
+    }
+
+    /// Returns the entry at position `index`.
+    pub fn get_index(&self, index: usize) -> Option<(&K, &V)> {
+        self.entries.get(index).map(|bucket| (&bucket.key, &bucket.value))
+    }
+
+    /// Returns the entry at position `index`, with a mutable value.
+    pub fn get_index_mut(&mut self, index: usize) -> Option<(&K, &mut V)> {
+        self.entries.get_mut(index).map(|bucket| (&bucket.key, &mut bucket.value))
+    }
+
+    /// Moves the entry at position `from` to position `to`, shifting the
+    /// entries in between by one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either position is out of bounds.
+    pub fn move_index(&mut self, from: usize, to: usize) {
+        assert!(from < self.len() && to < self.len(), "index out of bounds");
+        // Only the positions between `from` and `to` change. Each one is
+        // rewritten once the old value it replaces is gone, so that the
+        // lookups of later ones never see it twice.
+        let moved = self.slot_of(from);
+        if from < to {
+            self.entries[from..=to].rotate_left(1);
+            for index in from..to {
+                self.reindex(index, index + 1);
+            }
+        } else {
+            self.entries[to..=from].rotate_right(1);
+            for index in (to + 1..=from).rev() {
+                self.reindex(index, index - 1);
+            }
+        }
+        *self.indices.get_mut(moved) = to;
+    }
+
+    /// Returns the bucket of the index table which holds position `index`.
+    fn slot_of(&self, index: usize) -> usize {
+        self.indices.find(self.entries[index].hash, |&i| i == index).expect("entry not indexed")
+    }
+
+    /// Points the index at position `index` for the entry which moved there
+    /// from position `old`.
+    fn reindex(&mut self, index: usize, old: usize) {
+        let hash = self.entries[index].hash;
+        let slot = self.indices.find(hash, |&i| i == old).expect("entry not indexed");
+        *self.indices.get_mut(slot) = index;
+    }
+
+    /// Sorts the entries with `compare`, which sees the key and value of
+    /// both entries. The sort is stable.
+    pub fn sort_by<F>(&mut self, mut compare: F)
+    where
+        F: FnMut(&K, &V, &K, &V) -> Ordering,
+    {
+        self.entries.sort_by(|a, b| compare(&a.key, &a.value, &b.key, &b.value));
+        self.rebuild_indices();
+    }
+
+    /// Sorts the entries by key.
+    pub fn sort_keys(&mut self)
+    where
+        K: Ord,
+    {
+        self.entries.sort_by(|a, b| a.key.cmp(&b.key));
+        self.rebuild_indices();
+    }
+
+    /// Points the index at every entry again after they were reordered.
+    fn rebuild_indices(&mut self) {
+        self.indices.clear();
+        for (index, bucket) in self.entries.iter().enumerate() {
+            self.indices.insert_no_grow(bucket.hash, index);
+        }
+    }
+
+    /// Removes every entry, keeping the allocated memory.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.indices.clear();
+    }
+}
+
+
+impl<K, V, S> IndexMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Returns the bucket of the index table which holds the position of
+    /// `key`.
+    fn find<Q>(&self, key: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find_hashed(self.hash_builder.hash_one(key), key)
+    }
+
+    /// Like `find`, for a `key` already hashed to `hash`.
+    fn find_hashed<Q>(&self, hash: u64, key: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        let entries = &self.entries;
+        self.indices.find(hash, |&i| entries[i].hash == hash && entries[i].key.borrow() == key)
+    }
+
+    /// Inserts a key-value pair. If the key was already present its value
+    /// is replaced and returned, and the entry keeps its position; otherwise
+    /// it goes last.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = self.hash_builder.hash_one(&key);
+        if let Some(slot) = self.find_hashed(hash, &key) {
+            let index = *self.indices.get(slot);
+            return Some(mem::replace(&mut self.entries[index].value, value));
+        }
+        let entries = &self.entries;
+        self.indices.reserve(1, |&i| entries[i].hash);
+        self.indices.insert_no_grow(hash, self.entries.len());
+        self.entries.push(Bucket { hash, key, value });
+        None
+    }
+
+    /// Returns the position of `key`.
+    pub fn get_index_of<Q>(&self, key: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find(key).map(|slot| *self.indices.get(slot))
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let index = self.get_index_of(key)?;
+        Some(&self.entries[index].value)
+    }
+
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let index = self.get_index_of(key)?;
+        Some(&mut self.entries[index].value)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.find(key).is_some()
+    }
+
+    /// Removes `key` and returns its value, moving the last entry into its
+    /// position. Takes constant time, but disturbs the order.
+    pub fn swap_remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let slot = self.find(key)?;
+        let index = self.indices.remove(slot);
+        let bucket = self.entries.swap_remove(index);
+        let moved = self.entries.len();
+        if index < moved {
+            self.reindex(index, moved);
+        }
+        Some(bucket.value)
+    }
+
+    /// Removes `key` and returns its value, shifting every later entry back
+    /// by one. Keeps the order, but takes time linear in the number of
+    /// later entries.
+    pub fn shift_remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let slot = self.find(key)?;
+        let index = self.indices.remove(slot);
+        let bucket = self.entries.remove(index);
+        // In increasing order, so that each old position is already gone from
+        // the index when the next entry takes it.
+        for i in index..self.entries.len() {
+            self.reindex(i, i + 1);
+        }
+        Some(bucket.value)
+    }
+}
+
+
+impl<K, V, S: Default> Default for IndexMap<K, V, S> {
+    fn default() -> Self {
+        IndexMap::with_hasher(S::default())
+    }
+}
+
+
+impl<K, V, S> Clone for IndexMap<K, V, S>
+where
+    K: Clone,
+    V: Clone,
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        IndexMap {
+            entries: self.entries.clone(),
+            indices: self.indices.clone(),
+            hash_builder: self.hash_builder.clone(),
+        }
+    }
+}
+
+
+impl<K, V, S> fmt::Debug for IndexMap<K, V, S>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+
+/// Maps are equal when they hold the same entries, in any order.
+impl<K, V, S> PartialEq for IndexMap<K, V, S>
+where
+    K: Hash + Eq,
+    V: PartialEq,
+    S: BuildHasher,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+
+impl<K, V, S> Eq for IndexMap<K, V, S>
+where
+    K: Hash + Eq,
+    V: Eq,
+    S: BuildHasher,
+{
+}
+
+
+impl<K, Q, V, S> Index<&Q> for IndexMap<K, V, S>
+where
+    K: Hash + Eq + Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+    S: BuildHasher,
+{
+    type Output = V;
+
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("no entry found for key")
+    }
+}
+
+
+pub struct Iter<'a, K: 'a, V: 'a> {
+    inner: slice::Iter<'a, Bucket<K, V>>,
+}
+
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|bucket| (&bucket.key, &bucket.value))
+    }
+}
+
+
+impl<'a, K, V, S> IntoIterator for &'a IndexMap<K, V, S> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+
+pub struct IterMut<'a, K: 'a, V: 'a> {
+    inner: slice::IterMut<'a, Bucket<K, V>>,
+}
+
+
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|bucket| (&bucket.key, &mut bucket.value))
+    }
+}
+
+
+impl<'a, K, V, S> IntoIterator for &'a mut IndexMap<K, V, S> {
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+
+pub struct IntoIter<K, V> {
+    inner: vec::IntoIter<Bucket<K, V>>,
+}
+
+
+impl<K, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|bucket| (bucket.key, bucket.value))
+    }
+}
+
+
+impl<K, V, S> IntoIterator for IndexMap<K, V, S> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter { inner: self.entries.into_iter() }
+    }
+}
+
+
+impl<K, V, S> FromIterator<(K, V)> for IndexMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher + Default,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = IndexMap::with_hasher(S::default());
+        map.extend(iter);
+        map
+    }
+}
+
+
+impl<K, V, S> Extend<(K, V)> for IndexMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys<V>(map: &IndexMap<&'static str, V>) -> Vec<&'static str> {
+        map.iter().map(|(&k, _)| k).collect()
+    }
+
+    /// Checks that the index points at every entry.
+    fn assert_consistent<V>(map: &IndexMap<&'static str, V>) {
+        assert_eq!(map.indices.len(), map.len());
+        for (index, (key, _)) in map.iter().enumerate() {
+            assert_eq!(map.get_index_of(key), Some(index));
+        }
+    }
+
+    fn abcde() -> IndexMap<&'static str, i32> {
+        vec![("a", 1), ("b", 2), ("c", 3), ("d", 4), ("e", 5)].into_iter().collect()
+    }
+
+    #[test]
+    fn insertion_order() {
+        let mut map = IndexMap::new();
+        for (i, key) in ["z", "y", "x", "w"].iter().enumerate() {
+            map.insert(*key, i);
+        }
+        assert_eq!(map.insert("y", 10), Some(1));
+        assert_eq!(keys(&map), ["z", "y", "x", "w"]);
+        assert_eq!(map["y"], 10);
+    }
+
+    #[test]
+    fn order_survives_growth() {
+        let mut map = IndexMap::new();
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        assert!(map.iter().map(|(&k, _)| k).eq(0..1000));
+    }
+
+    #[test]
+    fn get_index() {
+        let mut map = abcde();
+        assert_eq!(map.get_index(2), Some((&"c", &3)));
+        assert_eq!(map.get_index(5), None);
+        *map.get_index_mut(0).unwrap().1 = 10;
+        assert_eq!(map["a"], 10);
+    }
+
+    #[test]
+    fn get_index_of() {
+        let map = abcde();
+        assert_eq!(map.get_index_of("d"), Some(3));
+        assert_eq!(map.get_index_of("f"), None);
+    }
+
+    #[test]
+    fn swap_remove() {
+        let mut map = abcde();
+        assert_eq!(map.swap_remove("b"), Some(2));
+        assert_eq!(map.swap_remove("b"), None);
+        assert_eq!(keys(&map), ["a", "e", "c", "d"]);
+        assert_eq!(map.swap_remove("d"), Some(4));
+        assert_eq!(keys(&map), ["a", "e", "c"]);
+        assert_consistent(&map);
+    }
+
+    #[test]
+    fn shift_remove() {
+        let mut map = abcde();
+        assert_eq!(map.shift_remove("b"), Some(2));
+        assert_eq!(map.shift_remove("b"), None);
+        assert_eq!(keys(&map), ["a", "c", "d", "e"]);
+        assert_consistent(&map);
+    }
+
+    #[test]
+    fn move_index() {
+        let mut map = abcde();
+        map.move_index(1, 3);
+        assert_eq!(keys(&map), ["a", "c", "d", "b", "e"]);
+        assert_consistent(&map);
+        map.move_index(4, 0);
+        assert_eq!(keys(&map), ["e", "a", "c", "d", "b"]);
+        assert_consistent(&map);
+        map.move_index(2, 2);
+        assert_eq!(keys(&map), ["e", "a", "c", "d", "b"]);
+    }
+
+    #[test]
+    fn reorder_colliding() {
+        // With every key hashing the same, positions are all the index has
+        // to tell entries apart.
+        #[derive(Default)]
+        struct Same;
+
+        impl std::hash::Hasher for Same {
+            fn finish(&self) -> u64 {
+                0
+            }
+
+            fn write(&mut self, _: &[u8]) {}
+        }
+
+        let mut map = IndexMap::with_hasher(std::hash::BuildHasherDefault::<Same>::default());
+        let mut model: Vec<u32> = (0..20).collect();
+        for &key in &model {
+            map.insert(key, ());
+        }
+        for &(from, to) in &[(0, 19), (19, 0), (3, 12), (12, 3), (5, 6), (6, 5), (7, 7)] {
+            map.move_index(from, to);
+            let key = model.remove(from);
+            model.insert(to, key);
+        }
+        for &key in &[0, 10, 19, 4] {
+            map.shift_remove(&key);
+            model.retain(|&k| k != key);
+        }
+        assert!(map.iter().map(|(&k, _)| k).eq(model.iter().copied()));
+        for (index, key) in model.iter().enumerate() {
+            assert_eq!(map.get_index_of(key), Some(index));
+        }
+    }
+
+    #[test]
+    fn sort() {
+        let mut map: IndexMap<_, _> = vec![("c", 1), ("a", 3), ("b", 2)].into_iter().collect();
+        map.sort_keys();
+        assert_eq!(keys(&map), ["a", "b", "c"]);
+        assert_consistent(&map);
+        map.sort_by(|_, v1, _, v2| v1.cmp(v2));
+        assert_eq!(keys(&map), ["c", "b", "a"]);
+        assert_consistent(&map);
+    }
+
+    #[test]
+    fn iter_mut() {
+        let mut map = abcde();
+        for (_, value) in map.iter_mut() {
+            *value *= 2;
+        }
+        assert_eq!(map.into_iter().collect::<Vec<_>>(), [("a", 2), ("b", 4), ("c", 6), ("d", 8), ("e", 10)]);
+    }
+
+    #[test]
+    fn eq_ignores_order() {
+        let mut map = abcde();
+        map.move_index(0, 4);
+        assert_eq!(map, abcde());
+        map.insert("f", 6);
+        assert_ne!(map, abcde());
+    }
+
+    #[test]
+    fn debug() {
+        let map: IndexMap<_, _> = vec![("b", 1), ("a", 2)].into_iter().collect();
+        assert_eq!(format!("{:?}", map), r#"{"b": 1, "a": 2}"#);
+    }
+}
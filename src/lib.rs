@@ -1,59 +1,461 @@
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::iter::FromIterator;
 use std::mem;
 use std::borrow::Borrow;
+use std::fmt;
+use std::ops::Index;
 
+pub mod atomic;
+pub mod bimap;
+pub mod codec;
+pub mod concurrent;
+pub mod index_map;
+pub mod lru;
+pub mod multimap;
+pub mod persistent;
+mod policy;
+mod raw;
+#[cfg(feature = "serde")]
+pub mod serde;
+pub mod set;
+pub mod snapshot;
+#[cfg(test)]
+mod test_rng;
+
+pub use atomic::AtomicHashMap;
+pub use bimap::BiHashMap;
+pub use concurrent::ConcurrentHashMap;
+pub use index_map::IndexMap;
+pub use lru::LruCache;
+pub use multimap::HashMultiMap;
+pub use persistent::PersistentHashMap;
+pub use policy::GrowthPolicy;
+pub use set::HashSet;
+pub use snapshot::SnapshotMap;
+use raw::{Migration, RawTable};
+
+
+/// Number of old buckets each operation moves during an incremental
+/// resize.
+const MIGRATE_NBUCKETS: usize = 16;
 
-const INITIAL_NBUCKETS: usize = 1;
+
+/// Returns a closure which hashes the key of an entry with `hash_builder`,
+/// for the table to use when it moves entries around.
+fn make_hasher<K, V, S>(hash_builder: &S) -> impl Fn(&(K, V)) -> u64 + '_
+where
+    K: Hash,
+    S: BuildHasher,
+{
+    move |(key, _)| hash_builder.hash_one(key)
+}
 
 
-pub struct HashMap<K, V> {
-    buckets: Vec<Vec<(K, V)>>,
-    items: usize,
+/// A hash map using open addressing, probed like a SwissTable.
+///
+/// All entries live in one table of buckets, probed a group of control
+/// bytes at a time; see the `raw` module for the details. Keys are hashed
+/// with the `BuildHasher` `S`, which defaults to std's SipHash-based
+/// `RandomState`.
+///
+/// When the table grows and shrinks is up to its `GrowthPolicy`. By
+/// default it is resized all at once when it fills up. With
+/// `set_incremental_resize`, the entries are instead moved into the new
+/// table a few buckets at a time by later operations.
+pub struct HashMap<K, V, S = RandomState> {
+    table: RawTable<(K, V)>,
+    /// The table being moved into `table` by an incremental resize.
+    migration: Option<Migration<(K, V)>>,
+    incremental_resize: bool,
+    hash_builder: S,
 }
 
 
-impl<K, V> HashMap<K, V> {
+impl<K, V> HashMap<K, V, RandomState> {
     pub fn new() -> Self {
+        HashMap::with_hasher(RandomState::new())
+    }
+
+    /// Creates an empty map able to hold `capacity` items without
+    /// resizing.
+    pub fn with_capacity(capacity: usize) -> Self {
+        HashMap::with_capacity_and_hasher(capacity, RandomState::new())
+    }
+}
+
+
+impl<K, V, S> HashMap<K, V, S> {
+    /// Creates an empty map which will use `hash_builder` to hash keys.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        HashMap {
+            table: RawTable::new(),
+            migration: None,
+            incremental_resize: false,
+            hash_builder,
+        }
+    }
+
+    /// Creates an empty map able to hold `capacity` items without
+    /// resizing, which will use `hash_builder` to hash keys.
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
+        HashMap {
+            table: RawTable::with_capacity(capacity),
+            migration: None,
+            incremental_resize: false,
+            hash_builder,
+        }
+    }
+
+    /// Returns a reference to the map's `BuildHasher`.
+    pub fn hasher(&self) -> &S {
+        &self.hash_builder
+    }
+
+    /// Number of items the map can hold without resizing.
+    pub fn capacity(&self) -> usize {
+        self.table.capacity()
+    }
+
+    /// Returns the policy deciding when the map grows and shrinks.
+    pub fn growth_policy(&self) -> GrowthPolicy {
+        self.table.policy()
+    }
+
+    /// Whether the map keeps the hash of every key; see `set_cache_hashes`.
+    pub fn caches_hashes(&self) -> bool {
+        self.table.caches_hashes()
+    }
+
+    /// Whether the map resizes incrementally; see `set_incremental_resize`.
+    pub fn incremental_resize(&self) -> bool {
+        self.incremental_resize
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.into_iter()
+    }
+
+    /// Iterates over the entries, with mutable references to the values.
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        self.into_iter()
+    }
+
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V> {
+        ValuesMut { inner: self.iter_mut() }
+    }
+
+    /// Keeps only the entries for which `f` returns `true`.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        let mut sweep = self.table.sweep();
+        while sweep.next_if(|(key, value)| !f(key, value)).is_some() {}
+        if let Some(ref mut migration) = self.migration {
+            let mut sweep = migration.table_mut().sweep();
+            while sweep.next_if(|(key, value)| !f(key, value)).is_some() {}
+        }
+    }
+
+    /// Removes every entry, returning them as an iterator. The buckets stay
+    /// allocated for reuse.
+    ///
+    /// The map is empty even if the iterator is dropped before it finishes.
+    pub fn drain(&mut self) -> Drain<'_, K, V> {
+        Drain {
+            inner: self.table.drain(),
+            old: self.migration.take().map(|migration| migration.into_table().into_iter()),
+        }
+    }
+
+    /// Returns an iterator which removes and yields the entries for which
+    /// `pred` returns `true`.
+    ///
+    /// Entries are only examined as the iterator advances; if it is dropped
+    /// early, the remaining entries stay in the map.
+    pub fn extract_if<F>(&mut self, pred: F) -> ExtractIf<'_, K, V, F>
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        ExtractIf {
+            inner: self.table.sweep(),
+            old: self.migration.as_mut().map(|migration| migration.table_mut().sweep()),
+            pred,
+        }
+    }
+
+    /// Removes every entry, keeping the buckets allocated.
+    pub fn clear(&mut self) {
+        self.migration = None;
+        self.table.clear();
+    }
+}
+
+
+impl<K, V, S: Default> Default for HashMap<K, V, S> {
+    fn default() -> Self {
+        HashMap::with_hasher(S::default())
+    }
+}
+
+
+impl<K, V, S> Clone for HashMap<K, V, S>
+where
+    K: Clone,
+    V: Clone,
+    S: Clone,
+{
+    fn clone(&self) -> Self {
         HashMap {
-            buckets: Vec::new(),
-            items: 0,
+            table: self.table.clone(),
+            migration: self.migration.clone(),
+            incremental_resize: self.incremental_resize,
+            hash_builder: self.hash_builder.clone(),
         }
     }
+
+    /// Reuses the allocation of `self`'s table where possible.
+    fn clone_from(&mut self, source: &Self) {
+        self.table.clone_from(&source.table);
+        self.migration.clone_from(&source.migration);
+        self.incremental_resize = source.incremental_resize;
+        self.hash_builder.clone_from(&source.hash_builder);
+    }
+}
+
+
+impl<K, V, S> fmt::Debug for HashMap<K, V, S>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+
+/// Two maps are equal when they hold the same entries, whatever their
+/// bucket layout.
+impl<K, V, S> PartialEq for HashMap<K, V, S>
+where
+    K: Hash + Eq,
+    V: PartialEq,
+    S: BuildHasher,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self.iter().all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+
+impl<K, V, S> Eq for HashMap<K, V, S>
+where
+    K: Hash + Eq,
+    V: Eq,
+    S: BuildHasher,
+{
+}
+
+
+impl<K, Q, V, S> Index<&Q> for HashMap<K, V, S>
+where
+    K: Hash + Eq + Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+    S: BuildHasher,
+{
+    type Output = V;
+
+    /// Panics if `key` is not in the map.
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("no entry found for key")
+    }
 }
 
 
-impl<K, V> HashMap<K, V>
+impl<K, V, S> HashMap<K, V, S>
 where
     K: Hash + Eq,
+    S: BuildHasher,
 {
-  fn bucket<Q>(&self, key: &Q) -> usize
+  fn hash<Q>(&self, key: &Q) -> u64
   where
     K: Borrow<Q>,
     Q: Hash + Eq + ?Sized,
   {
-    let mut hasher = DefaultHasher::new();
-    key.hash(&mut hasher);
-    (hasher.finish() % self.buckets.len() as u64) as usize
+    self.hash_builder.hash_one(key)
   }
 
-  pub fn insert(&mut self, key: K, value: V) -> Option<V> {
-    if self.buckets.is_empty() || self.items > 3 * self.buckets.len() / 4 {
-        self.resize();
+  /// Finds `key`, in the old table too during an incremental resize, and
+  /// returns the table holding it along with its index there.
+  fn find<Q>(&self, key: &Q) -> Option<(&RawTable<(K, V)>, usize)>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let hash = self.hash(key);
+    let eq = |(ekey, _): &(K, V)| ekey.borrow() == key;
+    if let Some(index) = self.table.find(hash, eq) {
+      return Some((&self.table, index));
+    }
+    let old = self.migration.as_ref()?.table();
+    old.find(hash, eq).map(|index| (old, index))
+  }
+
+  fn find_mut<Q>(&mut self, key: &Q) -> Option<(&mut RawTable<(K, V)>, usize)>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let hash = self.hash(key);
+    let eq = |(ekey, _): &(K, V)| ekey.borrow() == key;
+    if let Some(index) = self.table.find(hash, eq) {
+      return Some((&mut self.table, index));
+    }
+    let old = self.migration.as_mut()?.table_mut();
+    old.find(hash, eq).map(move |index| (old, index))
+  }
+
+  /// Sets the policy deciding when the map grows and shrinks, resizing it
+  /// to match right away.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the load factor is not in `(0, 1)`, the growth multiplier
+  /// is not more than 1, or the shrink threshold is not below the load
+  /// factor.
+  pub fn set_growth_policy(&mut self, policy: GrowthPolicy) {
+    policy.validate();
+    let hasher = make_hasher(&self.hash_builder);
+    if let Some(migration) = self.migration.take() {
+      migration.finish(&mut self.table, &hasher);
+    }
+    self.table.set_policy(policy, hasher);
+  }
+
+  /// Chooses whether the map keeps the hash of every key next to its
+  /// entry, at the cost of 8 bytes per bucket.
+  ///
+  /// Resizing then moves entries without hashing their keys again, and
+  /// lookups only compare keys whose full hash matches. This pays off for
+  /// keys which are slow to hash or compare, such as long strings.
+  pub fn set_cache_hashes(&mut self, cache_hashes: bool) {
+    let hasher = make_hasher(&self.hash_builder);
+    if let Some(migration) = self.migration.take() {
+      migration.finish(&mut self.table, &hasher);
+    }
+    self.table.set_cache_hashes(cache_hashes, hasher);
+  }
+
+  /// Chooses whether the map resizes incrementally.
+  ///
+  /// When enabled, a full table is still replaced by a bigger one right
+  /// away, but its entries are only moved over `MIGRATE_NBUCKETS` buckets
+  /// at a time by each later `insert`, `entry`, `get_mut` and `remove`.
+  /// Lookups check both tables until the move is done. Lookups through
+  /// `&self` cannot move anything, so they don't help it along.
+  ///
+  /// Disabling it finishes any resize under way.
+  pub fn set_incremental_resize(&mut self, incremental: bool) {
+    self.incremental_resize = incremental;
+    if !incremental {
+      if let Some(migration) = self.migration.take() {
+        migration.finish(&mut self.table, make_hasher(&self.hash_builder));
+      }
+    }
+  }
+
+  /// Makes room for `additional` more entries, so that inserting them
+  /// won't resize. If incremental resizing is enabled, the entries are
+  /// moved to a bigger table by later operations rather than right away.
+  pub fn reserve(&mut self, additional: usize) {
+    let hasher = make_hasher(&self.hash_builder);
+    if let Some(migration) = self.migration.take() {
+      let pending = migration.table().len();
+      let needed = additional.checked_add(pending).expect("capacity overflow");
+      if self.table.has_room(needed) {
+        self.migration = Some(migration);
+        return;
+      }
+      // The new table filled up before the old one was emptied, so the
+      // rest has to move now.
+      self.table.reserve(needed, &hasher);
+      migration.finish(&mut self.table, &hasher);
     }
 
-    let bucket = self.bucket(&key);
-    let bucket = &mut self.buckets[bucket];
+    if self.incremental_resize && self.table.len() > 0 && !self.table.has_room(additional) {
+      let new = self.table.empty_like(self.table.resize_target(additional));
+      let old = mem::replace(&mut self.table, new);
+      self.migration = Some(Migration::new(old));
+    } else {
+      self.table.reserve(additional, hasher);
+    }
+  }
+
+  /// Shrinks the table as much as possible while it still holds the
+  /// current entries. Finishes any incremental resize under way.
+  pub fn shrink_to_fit(&mut self) {
+    self.shrink_to(0);
+  }
+
+  /// Shrinks the table as much as possible while it still holds
+  /// `min_capacity` entries, or the current ones if there are more.
+  /// Finishes any incremental resize under way.
+  pub fn shrink_to(&mut self, min_capacity: usize) {
+    let hasher = make_hasher(&self.hash_builder);
+    if let Some(migration) = self.migration.take() {
+      migration.finish(&mut self.table, &hasher);
+    }
+    self.table.shrink_to(min_capacity, hasher);
+  }
+
+  /// Moves the next few buckets of an incremental resize, if one is under
+  /// way. The new table always has room for every entry left in the old
+  /// one.
+  fn migrate(&mut self) {
+    if let Some(ref mut migration) = self.migration {
+      if migration.step(&mut self.table, MIGRATE_NBUCKETS, make_hasher(&self.hash_builder)) {
+        self.migration = None;
+      }
+    }
+  }
+
+  pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+    match self.entry(key) {
+      Entry::Occupied(mut entry) => Some(entry.insert(value)),
+      Entry::Vacant(entry) => {
+        entry.insert(value);
+        None
+      }
+    }
+  }
 
-    self.items += 1;
-    for &mut (ref ekey, ref mut evalue) in bucket.iter_mut() {
-      if ekey == &key {
-          return Some(mem::replace(evalue, value));
+  /// Gets the given key's entry for in-place manipulation.
+  ///
+  /// Only a vacant entry makes the map grow, before it is handed out, so
+  /// that inserting through it never has to resize, while updating a key
+  /// already there never does.
+  pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
+    let hash = self.hash(&key);
+    if let Some(ref mut migration) = self.migration {
+      // Bring the entry over from the old table, so that entries only ever
+      // deal with the new one. The new table always has room for every
+      // entry still to come.
+      let old = migration.table_mut();
+      if let Some(index) = old.find(hash, |(ekey, _)| *ekey == key) {
+        let entry = old.remove(index);
+        self.table.insert_no_grow(hash, entry);
       }
     }
+    if let Some(index) = self.table.find(hash, |(ekey, _)| *ekey == key) {
+      return Entry::Occupied(OccupiedEntry { key, table: &mut self.table, index });
+    }
 
-    bucket.push((key, value));
-    None
+    self.reserve(1);
+    self.migrate();
+    Entry::Vacant(VacantEntry { hash, key, table: &mut self.table })
   }
 
   pub fn get<Q>(&self, key: &Q) -> Option<&V> 
@@ -61,30 +463,30 @@ where
     K: Borrow<Q>,
     Q: Hash + Eq + ?Sized,
   {
-    let bucket = self.bucket(key);
-    self.buckets[bucket]
-      .iter()
-      .find(|&(ref ekey, _)| ekey.borrow() == key)
-      .map(|&(_, ref v)| v)
+    let (table, index) = self.find(key)?;
+    Some(&table.get(index).1)
   }
 
-  fn resize(&mut self) {
-    let target_size = match self.buckets.len() {
-        0 => INITIAL_NBUCKETS,
-        n => 2 * n,
-    };
-
-    let mut new_buckets = Vec::with_capacity(target_size);
-    new_buckets.extend((0..target_size).map(|_| Vec::new()));
-
-    for (key, value) in self.buckets.iter_mut().flat_map(|bucket| bucket.drain(..)) {
-      let mut hasher = DefaultHasher::new();
-      key.hash(&mut hasher);
-      let bucket = (hasher.finish() % new_buckets.len() as u64) as usize;
-      new_buckets[bucket].push((key, value));
-    }
+  /// Returns the stored key along with the value, for keys which compare
+  /// equal without being identical.
+  pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let (table, index) = self.find(key)?;
+    let (k, v) = table.get(index);
+    Some((k, v))
+  }
 
-    let _ = mem::replace(&mut self.buckets, new_buckets);
+  pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    self.migrate();
+    let (table, index) = self.find_mut(key)?;
+    Some(&mut table.get_mut(index).1)
   }
 
   pub fn remove<Q>(&mut self, key: &Q) -> Option<V> 
@@ -92,19 +494,30 @@ where
     K: Borrow<Q>,
     Q: Hash + Eq + ?Sized,
   {
-    let bucket = self.bucket(key);
-    let bucket = &mut self.buckets[bucket];
-    let i = bucket.iter().position(|&(ref ekey, _)| ekey.borrow() == key)?;
-    self.items -= 1;
-    Some(bucket.swap_remove(i).1)
+    self.remove_entry(key).map(|(_, value)| value)
+  }
+
+  /// Removes a key from the map, returning the stored key and value.
+  pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    self.migrate();
+    let (table, index) = self.find_mut(key)?;
+    let entry = table.remove(index);
+    if self.migration.is_none() {
+      self.table.shrink_if_sparse(make_hasher(&self.hash_builder));
+    }
+    Some(entry)
   }
 
   pub fn len(&self) -> usize {
-    self.items
+    self.table.len() + self.migration.as_ref().map_or(0, |migration| migration.table().len())
   }
 
   pub fn is_empty(&self) -> bool {
-    self.items == 0
+    self.len() == 0
   }
 
   pub fn contains_key<Q>(&self, key: &Q) -> bool 
@@ -118,93 +531,389 @@ where
 
 
 pub struct Iter<'a, K: 'a, V: 'a> {
-  map: &'a HashMap<K, V>,
-  bucket: usize,
-  at: usize,
+  inner: raw::Iter<'a, (K, V)>,
+  /// The entries left in the old table during an incremental resize.
+  old: Option<raw::Iter<'a, (K, V)>>,
 }
 
 
 impl<'a, K, V> Iterator for Iter<'a, K, V> {
   type Item = (&'a K, &'a V);
   fn next(&mut self) -> Option<Self::Item> {
-    loop {
-      match self.map.buckets.get(self.bucket) {
-        Some(bucket) => {
-          match bucket.get(self.at) {
-            Some(&(ref k, ref v)) => {
-              // Move along self.at and self.bucket
-              self.at += 1;
-              break Some((k, v));
-            }
-            None => {
-              self.bucket += 1;
-              self.at = 0;
-              continue;
-            }
-          }
-        }
-        None => break None,
-      }
-    }
+    let (k, v) = self.inner.next().or_else(|| self.old.as_mut()?.next())?;
+    Some((k, v))
   }
 }
 
 
-impl<'a, K, V> IntoIterator for &'a HashMap<K, V> {
+impl<'a, K, V, S> IntoIterator for &'a HashMap<K, V, S> {
   type Item = (&'a K, &'a V);
   type IntoIter = Iter<'a, K, V>;
   fn into_iter(self) -> Self::IntoIter {
     Iter {
-      map: self,
-      bucket: 0,
-      at: 0,
+      inner: self.table.iter(),
+      old: self.migration.as_ref().map(|migration| migration.table().iter()),
     }
   }
 }
 
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+pub struct IterMut<'a, K: 'a, V: 'a> {
+  inner: raw::IterMut<'a, (K, V)>,
+  old: Option<raw::IterMut<'a, (K, V)>>,
+}
 
-    #[test]
-    fn insert() {
-        let mut map = HashMap::new();
-        map.insert("foo", 42);
-        assert_eq!(map.get(&"foo"), Some(&42));
-        //assert_eq!(map.remove(&"foo"), None);
-    }
 
-    #[test]
-    fn remove() {
-        let mut map = HashMap::new();
-        map.insert("foo", 42);
-        map.remove(&"foo");
-        assert_eq!(map.get(&"foo"), None);
+impl<'a, K, V> Iterator for IterMut<'a, K, V> {
+  type Item = (&'a K, &'a mut V);
+  fn next(&mut self) -> Option<Self::Item> {
+    let (k, v) = self.inner.next().or_else(|| self.old.as_mut()?.next())?;
+    Some((&*k, v))
+  }
+}
+
+
+impl<'a, K, V, S> IntoIterator for &'a mut HashMap<K, V, S> {
+  type Item = (&'a K, &'a mut V);
+  type IntoIter = IterMut<'a, K, V>;
+  fn into_iter(self) -> Self::IntoIter {
+    IterMut {
+      inner: self.table.iter_mut(),
+      old: self.migration.as_mut().map(|migration| migration.table_mut().iter_mut()),
     }
+  }
+}
 
-    #[test]
-    fn is_empty() {
-        let map : HashMap<u8, u8> = HashMap::new();
-        assert!(map.is_empty());
+
+pub struct ValuesMut<'a, K: 'a, V: 'a> {
+  inner: IterMut<'a, K, V>,
+}
+
+
+impl<'a, K, V> Iterator for ValuesMut<'a, K, V> {
+  type Item = &'a mut V;
+  fn next(&mut self) -> Option<Self::Item> {
+    self.inner.next().map(|(_, v)| v)
+  }
+}
+
+
+pub struct IntoIter<K, V> {
+  inner: raw::IntoIter<(K, V)>,
+  old: Option<raw::IntoIter<(K, V)>>,
+}
+
+
+impl<K, V> Iterator for IntoIter<K, V> {
+  type Item = (K, V);
+  fn next(&mut self) -> Option<Self::Item> {
+    self.inner.next().or_else(|| self.old.as_mut()?.next())
+  }
+}
+
+
+impl<K, V, S> IntoIterator for HashMap<K, V, S> {
+  type Item = (K, V);
+  type IntoIter = IntoIter<K, V>;
+  fn into_iter(self) -> Self::IntoIter {
+    IntoIter {
+      inner: self.table.into_iter(),
+      old: self.migration.map(|migration| migration.into_table().into_iter()),
     }
+  }
+}
 
-    #[test]
-    fn len() {
-        let mut map = HashMap::new();
-        map.insert("foo", 42);
-        assert_eq!(map.len(), 1);
+
+impl<K, V, S> FromIterator<(K, V)> for HashMap<K, V, S>
+where
+  K: Hash + Eq,
+  S: BuildHasher + Default,
+{
+  fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+    let mut map = HashMap::with_hasher(S::default());
+    map.extend(iter);
+    map
+  }
+}
+
+
+impl<K, V, S> Extend<(K, V)> for HashMap<K, V, S>
+where
+  K: Hash + Eq,
+  S: BuildHasher,
+{
+  fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+    let iter = iter.into_iter();
+    // Duplicate keys are likely when extending a non-empty map, so only
+    // reserve for half of them then.
+    let additional = match self.len() {
+      0 => iter.size_hint().0,
+      _ => iter.size_hint().0.div_ceil(2),
+    };
+    self.reserve(additional);
+    for (key, value) in iter {
+      self.insert(key, value);
     }
+  }
+}
 
-    #[test]
-    fn contains() {
-        let mut map = HashMap::new();
-        map.insert("foo", 42);
-        assert!(map.contains_key(&"foo"));
+
+impl<'a, K, V, S> Extend<(&'a K, &'a V)> for HashMap<K, V, S>
+where
+  K: Hash + Eq + Copy,
+  V: Copy,
+  S: BuildHasher,
+{
+  fn extend<I: IntoIterator<Item = (&'a K, &'a V)>>(&mut self, iter: I) {
+    self.extend(iter.into_iter().map(|(&key, &value)| (key, value)));
+  }
+}
+
+
+impl<K, V, const N: usize> From<[(K, V); N]> for HashMap<K, V, RandomState>
+where
+  K: Hash + Eq,
+{
+  fn from(entries: [(K, V); N]) -> Self {
+    IntoIterator::into_iter(entries).collect()
+  }
+}
+
+
+pub struct Drain<'a, K: 'a, V: 'a> {
+  inner: raw::Drain<'a, (K, V)>,
+  old: Option<raw::IntoIter<(K, V)>>,
+}
+
+
+impl<'a, K, V> Iterator for Drain<'a, K, V> {
+  type Item = (K, V);
+  fn next(&mut self) -> Option<Self::Item> {
+    self.inner.next().or_else(|| self.old.as_mut()?.next())
+  }
+}
+
+
+pub struct ExtractIf<'a, K: 'a, V: 'a, F> {
+  inner: raw::Sweep<'a, (K, V)>,
+  old: Option<raw::Sweep<'a, (K, V)>>,
+  pred: F,
+}
+
+
+impl<'a, K, V, F> Iterator for ExtractIf<'a, K, V, F>
+where
+  F: FnMut(&K, &mut V) -> bool,
+{
+  type Item = (K, V);
+  fn next(&mut self) -> Option<Self::Item> {
+    let pred = &mut self.pred;
+    if let Some(entry) = self.inner.next_if(|(k, v)| pred(k, v)) {
+      return Some(entry);
     }
+    self.old.as_mut()?.next_if(|(k, v)| pred(k, v))
+  }
+}
 
-    #[test]
-    fn iter() {
+
+/// A view into a single entry of a map, obtained from `HashMap::entry`.
+pub enum Entry<'a, K: 'a, V: 'a> {
+  Occupied(OccupiedEntry<'a, K, V>),
+  Vacant(VacantEntry<'a, K, V>),
+}
+
+
+/// An entry whose key is already in the map.
+pub struct OccupiedEntry<'a, K: 'a, V: 'a> {
+  /// The key the entry was looked up with, equal to the one in the map.
+  key: K,
+  table: &'a mut RawTable<(K, V)>,
+  index: usize,
+}
+
+
+/// An entry whose key is not in the map yet.
+pub struct VacantEntry<'a, K: 'a, V: 'a> {
+  hash: u64,
+  key: K,
+  table: &'a mut RawTable<(K, V)>,
+}
+
+
+impl<'a, K, V> Entry<'a, K, V> {
+  /// Inserts `default` if the entry is vacant, and returns the value.
+  pub fn or_insert(self, default: V) -> &'a mut V {
+    match self {
+      Entry::Occupied(entry) => entry.into_mut(),
+      Entry::Vacant(entry) => entry.insert(default),
+    }
+  }
+
+  /// Inserts the result of `default` if the entry is vacant, and returns
+  /// the value.
+  pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+    match self {
+      Entry::Occupied(entry) => entry.into_mut(),
+      Entry::Vacant(entry) => entry.insert(default()),
+    }
+  }
+
+  /// Like `or_insert_with`, but `default` is given the entry's key.
+  pub fn or_insert_with_key<F: FnOnce(&K) -> V>(self, default: F) -> &'a mut V {
+    match self {
+      Entry::Occupied(entry) => entry.into_mut(),
+      Entry::Vacant(entry) => {
+        let value = default(&entry.key);
+        entry.insert(value)
+      }
+    }
+  }
+
+  /// Runs `f` on the value if the entry is occupied.
+  pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+    match self {
+      Entry::Occupied(mut entry) => {
+        f(entry.get_mut());
+        Entry::Occupied(entry)
+      }
+      Entry::Vacant(entry) => Entry::Vacant(entry),
+    }
+  }
+
+  pub fn key(&self) -> &K {
+    match *self {
+      Entry::Occupied(ref entry) => entry.key(),
+      Entry::Vacant(ref entry) => entry.key(),
+    }
+  }
+}
+
+
+impl<'a, K, V: Default> Entry<'a, K, V> {
+  /// Inserts `V::default()` if the entry is vacant, and returns the value.
+  pub fn or_default(self) -> &'a mut V {
+    self.or_insert_with(V::default)
+  }
+}
+
+
+impl<'a, K, V> OccupiedEntry<'a, K, V> {
+  pub fn key(&self) -> &K {
+    &self.table.get(self.index).0
+  }
+
+  pub fn get(&self) -> &V {
+    &self.table.get(self.index).1
+  }
+
+  pub fn get_mut(&mut self) -> &mut V {
+    &mut self.table.get_mut(self.index).1
+  }
+
+  /// Converts the entry into a reference to its value, borrowed from the map.
+  pub fn into_mut(self) -> &'a mut V {
+    &mut self.table.get_mut(self.index).1
+  }
+
+  /// Replaces the value, returning the old one.
+  pub fn insert(&mut self, value: V) -> V {
+    mem::replace(self.get_mut(), value)
+  }
+
+  pub fn remove(self) -> V {
+    self.remove_entry().1
+  }
+
+  /// Takes the key and value out of the map.
+  pub fn remove_entry(self) -> (K, V) {
+    self.table.remove(self.index)
+  }
+
+  /// Puts the key the entry was looked up with in the map, and returns the
+  /// equal key it replaces.
+  pub fn replace_key(self) -> K {
+    mem::replace(&mut self.table.get_mut(self.index).0, self.key)
+  }
+}
+
+
+impl<'a, K, V> VacantEntry<'a, K, V> {
+  pub fn key(&self) -> &K {
+    &self.key
+  }
+
+  pub fn into_key(self) -> K {
+    self.key
+  }
+
+  /// Inserts the entry's key with `value`, and returns the value.
+  pub fn insert(self, value: V) -> &'a mut V {
+    let index = self.table.insert_no_grow(self.hash, (self.key, value));
+    &mut self.table.get_mut(index).1
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert() {
+        let mut map = HashMap::new();
+        map.insert("foo", 42);
+        assert_eq!(map.get(&"foo"), Some(&42));
+        //assert_eq!(map.remove(&"foo"), None);
+    }
+
+    #[test]
+    fn remove() {
+        let mut map = HashMap::new();
+        map.insert("foo", 42);
+        map.remove(&"foo");
+        assert_eq!(map.get(&"foo"), None);
+    }
+
+    #[test]
+    fn empty_map() {
+        let mut map: HashMap<&str, u8> = HashMap::new();
+        assert_eq!(map.get(&"foo"), None);
+        assert_eq!(map.remove(&"foo"), None);
+        assert!(!map.contains_key(&"foo"));
+    }
+
+    #[test]
+    fn is_empty() {
+        let map : HashMap<u8, u8> = HashMap::new();
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn len() {
+        let mut map = HashMap::new();
+        map.insert("foo", 42);
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn len_after_replace() {
+        let mut map = HashMap::new();
+        map.insert("foo", 42);
+        assert_eq!(map.insert("foo", 43), Some(42));
+        assert_eq!(map.len(), 1);
+        map.remove(&"foo");
+        assert_eq!(map.len(), 0);
+    }
+
+    #[test]
+    fn contains() {
+        let mut map = HashMap::new();
+        map.insert("foo", 42);
+        assert!(map.contains_key(&"foo"));
+    }
+
+    #[test]
+    fn iter() {
       let mut map = HashMap::new();
       map.insert("foo", 42);
       map.insert("bar", 43);
@@ -220,4 +929,591 @@ mod tests {
       assert_eq!((&map).into_iter().count(), 3);
     }
 
+    #[test]
+    fn get_key_value() {
+        let mut map = HashMap::new();
+        map.insert(String::from("foo"), 42);
+        assert_eq!(map.get_key_value("foo"), Some((&String::from("foo"), &42)));
+        assert_eq!(map.get_key_value("bar"), None);
+    }
+
+    #[test]
+    fn remove_entry() {
+        let mut map = HashMap::new();
+        map.insert("foo", 42);
+        assert_eq!(map.remove_entry(&"foo"), Some(("foo", 42)));
+        assert_eq!(map.remove_entry(&"foo"), None);
+    }
+
+    #[test]
+    fn get_mut() {
+        let mut map = HashMap::new();
+        assert_eq!(map.get_mut(&"foo"), None);
+        map.insert("foo", 42);
+        *map.get_mut(&"foo").unwrap() += 1;
+        assert_eq!(map.get(&"foo"), Some(&43));
+    }
+
+    #[test]
+    fn iter_mut() {
+        let mut map = HashMap::new();
+        map.insert("foo", 42);
+        map.insert("bar", 43);
+        map.insert("buz", 44);
+        for (_, v) in &mut map {
+            *v += 1;
+        }
+        for v in map.values_mut() {
+            *v *= 2;
+        }
+        assert_eq!(map.get(&"foo"), Some(&86));
+        assert_eq!(map.get(&"bar"), Some(&88));
+        assert_eq!(map.get(&"buz"), Some(&90));
+        assert_eq!(map.iter_mut().count(), 3);
+    }
+
+    #[test]
+    fn into_iter() {
+        let mut map = HashMap::new();
+        map.insert("foo", 42);
+        map.insert("bar", 43);
+        let mut entries: Vec<_> = map.into_iter().collect();
+        entries.sort();
+        assert_eq!(entries, vec![("bar", 43), ("foo", 42)]);
+    }
+
+    #[test]
+    fn from_iter() {
+        let map: HashMap<_, _> = (0..100).map(|i| (i, i * i)).collect();
+        assert_eq!(map.len(), 100);
+        for i in 0..100 {
+            assert_eq!(map.get(&i), Some(&(i * i)));
+        }
+    }
+
+    #[test]
+    fn extend() {
+        let mut map = HashMap::from([("foo", 42)]);
+        map.extend(vec![("bar", 43), ("buz", 44)]);
+        map.extend(&HashMap::from([("qux", 45)]));
+        assert_eq!(map.len(), 4);
+        assert_eq!(map.get(&"foo"), Some(&42));
+        assert_eq!(map.get(&"bar"), Some(&43));
+        assert_eq!(map.get(&"buz"), Some(&44));
+        assert_eq!(map.get(&"qux"), Some(&45));
+    }
+
+    #[test]
+    fn extend_reserves() {
+        let mut map = HashMap::new();
+        map.extend((0..100).map(|i| (i, i)));
+        assert_eq!(map.table.nbuckets(), GrowthPolicy::default().buckets_for(100));
+    }
+
+    #[test]
+    fn retain() {
+        let mut map: HashMap<_, _> = (0..100).map(|i| (i, i)).collect();
+        map.retain(|&k, v| {
+            *v += 1;
+            k % 2 == 0
+        });
+        assert_eq!(map.len(), 50);
+        for i in 0..100 {
+            assert_eq!(map.get(&i).copied(), if i % 2 == 0 { Some(i + 1) } else { None });
+        }
+    }
+
+    #[test]
+    fn drain() {
+        let mut map: HashMap<_, _> = (0..100).map(|i| (i, i)).collect();
+        let nbuckets = map.table.nbuckets();
+        let mut entries: Vec<_> = map.drain().collect();
+        entries.sort();
+        assert_eq!(entries, (0..100).map(|i| (i, i)).collect::<Vec<_>>());
+        assert!(map.is_empty());
+        assert_eq!(map.table.nbuckets(), nbuckets);
+        assert_eq!(map.iter().count(), 0);
+    }
+
+    #[test]
+    fn drain_dropped_early() {
+        let mut map: HashMap<_, _> = (0..100).map(|i| (i, i)).collect();
+        assert_eq!(map.drain().take(3).count(), 3);
+        assert!(map.is_empty());
+        assert_eq!(map.iter().count(), 0);
+    }
+
+    #[test]
+    fn drain_leaked() {
+        let mut map: HashMap<_, _> = (0..100).map(|i| (i, i)).collect();
+        let mut drain = map.drain();
+        let drained: Vec<_> = drain.by_ref().take(3).collect();
+        mem::forget(drain);
+        assert_eq!(map.len(), 97);
+        assert_eq!(map.iter().count(), 97);
+        for i in 0..100 {
+            assert_eq!(map.contains_key(&i), !drained.contains(&(i, i)));
+        }
+    }
+
+    #[test]
+    fn extract_if() {
+        let mut map: HashMap<_, _> = (0..100).map(|i| (i, i)).collect();
+        let mut extracted: Vec<_> = map.extract_if(|&k, _| k % 3 == 0).collect();
+        extracted.sort();
+        assert_eq!(extracted, (0..100).filter(|i| i % 3 == 0).map(|i| (i, i)).collect::<Vec<_>>());
+        assert_eq!(map.len(), 66);
+        assert_eq!(map.iter().count(), 66);
+        assert!(map.iter().all(|(&k, _)| k % 3 != 0));
+    }
+
+    #[test]
+    fn extract_if_is_lazy() {
+        let mut map: HashMap<_, _> = (0..100).map(|i| (i, i)).collect();
+        assert_eq!(map.extract_if(|_, _| true).take(10).count(), 10);
+        assert_eq!(map.len(), 90);
+        assert_eq!(map.iter().count(), 90);
+    }
+
+    #[test]
+    fn clear() {
+        let mut map: HashMap<_, _> = (0..100).map(|i| (i, i)).collect();
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.get(&1), None);
+        map.insert(1, 1);
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn index() {
+        let mut map = HashMap::new();
+        map.insert("foo", 42);
+        assert_eq!(map[&"foo"], 42);
+    }
+
+    #[test]
+    #[should_panic(expected = "no entry found for key")]
+    fn index_missing() {
+        let mut map = HashMap::new();
+        map.insert("foo", 42);
+        let _ = map[&"bar"];
+    }
+
+    #[test]
+    fn debug() {
+        let mut map = HashMap::new();
+        map.insert("foo", 42);
+        assert_eq!(format!("{:?}", map), r#"{"foo": 42}"#);
+    }
+
+    #[test]
+    fn clone() {
+        let map: HashMap<_, _> = (0..100).map(|i| (i, i.to_string())).collect();
+        let mut other = map.clone();
+        assert_eq!(map, other);
+        other.insert(100, "100".to_string());
+        assert_ne!(map, other);
+        other.clone_from(&map);
+        assert_eq!(map, other);
+    }
+
+    #[test]
+    fn eq_ignores_layout() {
+        let map: HashMap<_, _> = (0..100).map(|i| (i, i)).collect();
+        let mut other = HashMap::with_capacity_and_hasher(1000, RandomState::new());
+        other.extend((0..100).rev().map(|i| (i, i)));
+        assert_ne!(map.table.nbuckets(), other.table.nbuckets());
+        assert_eq!(map, other);
+        other.insert(0, 1);
+        assert_ne!(map, other);
+    }
+
+    #[test]
+    fn default() {
+        let map: HashMap<u8, u8> = HashMap::default();
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn entry() {
+        let mut map = HashMap::new();
+        for word in "a b a c b a".split(' ') {
+            *map.entry(word).or_insert(0) += 1;
+        }
+        assert_eq!(map.get("a"), Some(&3));
+        assert_eq!(map.get("b"), Some(&2));
+        assert_eq!(map.get("c"), Some(&1));
+        assert_eq!(map.len(), 3);
+    }
+
+    #[test]
+    fn entry_or_insert_with_key() {
+        let mut map = HashMap::new();
+        assert_eq!(*map.entry("foo").or_insert_with_key(|k| k.len()), 3);
+        assert_eq!(*map.entry("foo").or_insert_with(|| 0), 3);
+        assert_eq!(*map.entry("bar").or_default(), 0);
+    }
+
+    #[test]
+    fn entry_and_modify() {
+        let mut map = HashMap::new();
+        map.entry("foo").and_modify(|v| *v += 1).or_insert(42);
+        assert_eq!(map.get("foo"), Some(&42));
+        map.entry("foo").and_modify(|v| *v += 1).or_insert(42);
+        assert_eq!(map.get("foo"), Some(&43));
+    }
+
+    #[test]
+    fn entry_occupied() {
+        let mut map = HashMap::new();
+        map.insert("foo", 42);
+        match map.entry("foo") {
+            Entry::Occupied(mut entry) => {
+                assert_eq!(entry.key(), &"foo");
+                assert_eq!(entry.insert(43), 42);
+                assert_eq!(entry.remove_entry(), ("foo", 43));
+            }
+            Entry::Vacant(_) => unreachable!(),
+        }
+        assert_eq!(map.get("foo"), None);
+        assert_eq!(map.len(), 0);
+    }
+
+    #[test]
+    fn entry_replace_key() {
+        let first = String::from("foo");
+        let second = String::from("foo");
+        let second_ptr = second.as_ptr();
+        let mut map = HashMap::new();
+        map.insert(first, 42);
+        match map.entry(second) {
+            Entry::Occupied(entry) => assert_eq!(entry.replace_key(), "foo"),
+            Entry::Vacant(_) => unreachable!(),
+        }
+        let (key, &value) = map.get_key_value("foo").unwrap();
+        assert_eq!(key.as_ptr(), second_ptr);
+        assert_eq!(value, 42);
+    }
+
+    #[test]
+    fn entry_vacant() {
+        let mut map = HashMap::new();
+        match map.entry("foo") {
+            Entry::Occupied(_) => unreachable!(),
+            Entry::Vacant(entry) => {
+                assert_eq!(entry.key(), &"foo");
+                *entry.insert(42) += 1;
+            }
+        }
+        assert_eq!(map.get("foo"), Some(&43));
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn entry_occupied_never_resizes() {
+        for incremental in [false, true] {
+            let mut map = HashMap::with_capacity(10);
+            map.set_incremental_resize(incremental);
+            let capacity = map.capacity();
+            for i in 0..capacity {
+                map.insert(i, i);
+            }
+            for i in 0..capacity {
+                *map.entry(i).or_insert(0) += 1;
+                map.insert(i, i);
+            }
+            assert_eq!(map.capacity(), capacity);
+            assert!(map.migration.is_none());
+        }
+    }
+
+    #[test]
+    fn incremental_resize() {
+        let mut map = HashMap::new();
+        map.set_incremental_resize(true);
+        for i in 0..780 {
+            map.insert(i, i);
+            assert_eq!(map.len(), i + 1);
+        }
+        assert!(map.migration.is_some());
+        assert_eq!(map.iter().count(), 780);
+        for i in 0..780 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+        let mut entries: Vec<_> = map.clone().into_iter().collect();
+        entries.sort();
+        assert_eq!(entries, (0..780).map(|i| (i, i)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn incremental_resize_is_bounded() {
+        let mut map = HashMap::new();
+        map.set_incremental_resize(true);
+        for i in 0..768 {
+            map.insert(i, i);
+        }
+        map.migration = None;
+        assert_eq!(map.table.capacity(), 768);
+
+        // The next insert swaps in a bigger table without moving more than
+        // a few buckets.
+        map.insert(768, 768);
+        let old = map.migration.as_ref().map(|migration| migration.table().len());
+        assert!(old.unwrap() >= 768 - MIGRATE_NBUCKETS);
+        for i in 0..=768 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+
+        // Later operations finish the move.
+        for i in 0..768 {
+            assert_eq!(map.remove(&i), Some(i));
+        }
+        assert!(map.migration.is_none());
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn incremental_resize_disabled() {
+        let mut map = HashMap::new();
+        map.set_incremental_resize(true);
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        map.set_incremental_resize(false);
+        assert!(map.migration.is_none());
+        assert_eq!(map.len(), 100);
+        for i in 0..100 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+    }
+
+    #[test]
+    fn with_capacity() {
+        let mut map = HashMap::with_capacity(100);
+        let capacity = map.capacity();
+        assert!(capacity >= 100);
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.capacity(), capacity);
+        assert_eq!(HashMap::<i32, i32>::with_capacity(0).capacity(), 0);
+    }
+
+    #[test]
+    fn reserve() {
+        let mut map = HashMap::new();
+        map.insert(0, 0);
+        map.reserve(1000);
+        let capacity = map.capacity();
+        assert!(capacity >= 1001);
+        for i in 1..1001 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.capacity(), capacity);
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn with_capacity_overflow() {
+        HashMap::<u8, u8>::with_capacity(usize::MAX);
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn reserve_overflow() {
+        let mut map = HashMap::new();
+        map.insert(0, 0);
+        map.reserve(usize::MAX);
+    }
+
+    #[test]
+    fn shrink_to_fit() {
+        let mut map: HashMap<_, _> = (0..1000).map(|i| (i, i)).collect();
+        map.retain(|&k, _| k < 10);
+        map.shrink_to_fit();
+        assert!(map.capacity() >= 10);
+        assert!(map.capacity() < 20);
+        for i in 0..10 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+        map.clear();
+        map.shrink_to_fit();
+        assert_eq!(map.capacity(), 0);
+    }
+
+    #[test]
+    fn shrink_to() {
+        let mut map: HashMap<_, _> = (0..1000).map(|i| (i, i)).collect();
+        map.retain(|&k, _| k < 10);
+        map.shrink_to(100);
+        let capacity = map.capacity();
+        assert!(capacity >= 100);
+        assert!(capacity < 1000);
+        map.shrink_to(5000);
+        assert_eq!(map.capacity(), capacity);
+        map.shrink_to(0);
+        assert!(map.capacity() < 20);
+        assert_eq!(map.len(), 10);
+    }
+
+    #[test]
+    fn shrink_during_incremental_resize() {
+        let mut map = HashMap::new();
+        map.set_incremental_resize(true);
+        for i in 0..780 {
+            map.insert(i, i);
+        }
+        assert!(map.migration.is_some());
+        map.shrink_to_fit();
+        assert!(map.migration.is_none());
+        assert_eq!(map.len(), 780);
+        for i in 0..780 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+    }
+
+    #[test]
+    fn growth_policy() {
+        let mut map = HashMap::new();
+        assert_eq!(map.growth_policy(), GrowthPolicy::default());
+        map.set_growth_policy(GrowthPolicy { max_load_factor: 0.95, ..GrowthPolicy::default() });
+        for i in 0..60 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.table.nbuckets(), 64);
+
+        // A lower load factor spreads the same entries over more buckets.
+        map.set_growth_policy(GrowthPolicy { max_load_factor: 0.5, ..GrowthPolicy::default() });
+        assert_eq!(map.table.nbuckets(), 128);
+        for i in 0..60 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+    }
+
+    #[test]
+    fn growth_policy_multiplier() {
+        let mut map = HashMap::new();
+        map.set_growth_policy(GrowthPolicy {
+            growth_multiplier: 4.0,
+            min_buckets: 16,
+            ..GrowthPolicy::default()
+        });
+        map.insert(0, 0);
+        assert_eq!(map.table.nbuckets(), 16);
+        for i in 1..13 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.table.nbuckets(), 64);
+    }
+
+    #[test]
+    fn growth_policy_shrinks() {
+        let mut map = HashMap::new();
+        map.set_growth_policy(GrowthPolicy {
+            shrink_threshold: Some(0.25),
+            ..GrowthPolicy::default()
+        });
+        for i in 0..1000 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.table.nbuckets(), 2048);
+        for i in 10..1000 {
+            map.remove(&i);
+        }
+        assert!(map.table.nbuckets() <= 32);
+        for i in 0..10 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "growth_multiplier")]
+    fn growth_policy_invalid() {
+        let mut map: HashMap<i32, i32> = HashMap::new();
+        map.set_growth_policy(GrowthPolicy { growth_multiplier: 1.0, ..GrowthPolicy::default() });
+    }
+
+    #[test]
+    fn with_hasher() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::BuildHasherDefault;
+
+        let mut map = HashMap::with_hasher(BuildHasherDefault::<DefaultHasher>::default());
+        map.insert(1u64, "one");
+        map.insert(2u64, "two");
+        assert_eq!(map.get(&1), Some(&"one"));
+        assert_eq!(map.get(&2), Some(&"two"));
+    }
+
+    #[test]
+    fn hasher() {
+        use std::hash::{BuildHasherDefault, Hasher};
+
+        // Sends every key to the same bucket.
+        #[derive(Default)]
+        struct Collide;
+        impl Hasher for Collide {
+            fn finish(&self) -> u64 { 0 }
+            fn write(&mut self, _: &[u8]) {}
+        }
+
+        let mut map = HashMap::with_hasher(BuildHasherDefault::<Collide>::default());
+        for i in 0..20 {
+            map.insert(i, i);
+        }
+        assert_eq!(map.hasher().hash_one(7), 0);
+        assert_eq!(map.len(), 20);
+        for i in 0..20 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+    }
+
+    #[test]
+    fn cache_hashes() {
+        use std::cell::Cell;
+        use std::hash::Hasher;
+
+        thread_local!(static HASHED: Cell<usize> = const { Cell::new(0) });
+
+        #[derive(PartialEq, Eq)]
+        struct Key(u32);
+        impl Hash for Key {
+            fn hash<H: Hasher>(&self, state: &mut H) {
+                HASHED.with(|hashed| hashed.set(hashed.get() + 1));
+                self.0.hash(state);
+            }
+        }
+
+        let mut map = HashMap::new();
+        map.set_cache_hashes(true);
+        assert!(map.caches_hashes());
+        for i in 0..1000 {
+            map.insert(Key(i), i);
+        }
+        // Once per insert, and never again when growing.
+        assert_eq!(HASHED.with(Cell::get), 1000);
+        for i in 0..1000 {
+            assert_eq!(map.get(&Key(i)), Some(&i));
+        }
+
+        map.set_cache_hashes(false);
+        assert!(!map.caches_hashes());
+        for i in 0..1000 {
+            assert_eq!(map.remove(&Key(i)), Some(i));
+        }
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn with_capacity_and_hasher() {
+        let mut map = HashMap::with_capacity_and_hasher(10, RandomState::new());
+        for i in 0..10 {
+            map.insert(i, i * 2);
+        }
+        for i in 0..10 {
+            assert_eq!(map.get(&i), Some(&(i * 2)));
+        }
+    }
+
 }
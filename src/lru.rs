@@ -0,0 +1,479 @@
+//! A fixed-capacity cache which evicts the least recently used entry.
+
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+
+use crate::raw::RawTable;
+
+
+/// Stands for "no node" in the recency links.
+const NIL: usize = usize::MAX;
+
+
+/// An entry of an `LruCache`, linked to the entries used right before and
+/// right after it.
+struct Node<K, V> {
+    hash: u64,
+    key: K,
+    value: V,
+    /// The more recently used neighbour.
+    prev: usize,
+    /// The less recently used neighbour.
+    next: usize,
+}
+
+
+/// A cache holding at most `cap` entries, which makes room for new ones by
+/// evicting the least recently used.
+///
+/// Entries live in one vector, threaded into a doubly-linked recency list
+/// by index, and are found through a table of those indices hashed by key
+/// with the `BuildHasher` `S`, the same way `HashMap` finds its entries.
+/// Keys are only stored in the vector, and every operation takes constant
+/// time.
+pub struct LruCache<K, V, S = RandomState> {
+    nodes: Vec<Node<K, V>>,
+    /// Indices into `nodes`, hashed by key.
+    table: RawTable<usize>,
+    /// The most recently used node.
+    head: usize,
+    /// The least recently used node.
+    tail: usize,
+    cap: usize,
+    hash_builder: S,
+}
+
+
+/// What `LruCache::put` pushed out of the cache to make room for an entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Displaced<K, V> {
+    /// The old value of the key, which was already in the cache.
+    Replaced(V),
+    /// The least recently used entry, evicted from a full cache. A cache
+    /// of capacity 0 evicts the new entry itself.
+    Evicted(K, V),
+}
+
+
+impl<K, V> LruCache<K, V, RandomState> {
+    /// Creates an empty cache which holds up to `cap` entries, with room
+    /// for all of them allocated up front.
+    pub fn new(cap: usize) -> Self {
+        LruCache::with_hasher(cap, RandomState::new())
+    }
+}
+
+
+impl<K, V, S> LruCache<K, V, S> {
+    /// Creates an empty cache which holds up to `cap` entries, with room
+    /// for all of them allocated up front, and will use `hash_builder` to
+    /// hash keys.
+    pub fn with_hasher(cap: usize, hash_builder: S) -> Self {
+        LruCache {
+            nodes: Vec::with_capacity(cap),
+            table: RawTable::with_capacity(cap),
+            head: NIL,
+            tail: NIL,
+            cap,
+            hash_builder,
+        }
+    }
+
+    /// Returns a reference to the cache's `BuildHasher`.
+    pub fn hasher(&self) -> &S {
+        &self.hash_builder
+    }
+
+    /// Number of entries the cache holds before it starts evicting.
+    pub fn cap(&self) -> usize {
+        self.cap
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Visits the entries from the most to the least recently used.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { nodes: &self.nodes, next: self.head }
+    }
+
+    /// Removes every entry.
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.table.clear();
+        self.head = NIL;
+        self.tail = NIL;
+    }
+
+    /// Takes node `index` out of the recency list.
+    fn unlink(&mut self, index: usize) {
+        let (prev, next) = (self.nodes[index].prev, self.nodes[index].next);
+        match prev {
+            NIL => self.head = next,
+            prev => self.nodes[prev].next = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            next => self.nodes[next].prev = prev,
+        }
+    }
+
+    /// Puts node `index`, which is not in the recency list, at its front.
+    fn push_front(&mut self, index: usize) {
+        self.nodes[index].prev = NIL;
+        self.nodes[index].next = self.head;
+        match self.head {
+            NIL => self.tail = index,
+            head => self.nodes[head].prev = index,
+        }
+        self.head = index;
+    }
+
+    /// Marks node `index` as the most recently used.
+    fn promote(&mut self, index: usize) {
+        if self.head != index {
+            self.unlink(index);
+            self.push_front(index);
+        }
+    }
+}
+
+
+impl<K, V, S> LruCache<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+  /// Returns the index of the node holding `key`.
+  fn find<Q>(&self, key: &Q) -> Option<usize>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let hash = self.hash_builder.hash_one(key);
+    let nodes = &self.nodes;
+    let slot = self.table.find(hash, |&i| nodes[i].hash == hash && nodes[i].key.borrow() == key)?;
+    Some(*self.table.get(slot))
+  }
+
+  /// Returns the value of `key`, and marks it as the most recently used.
+  pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let index = self.find(key)?;
+    self.promote(index);
+    Some(&self.nodes[index].value)
+  }
+
+  /// Returns the value of `key` for modification, and marks it as the most
+  /// recently used.
+  pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let index = self.find(key)?;
+    self.promote(index);
+    Some(&mut self.nodes[index].value)
+  }
+
+  /// Returns the value of `key`, without marking it as used.
+  pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let index = self.find(key)?;
+    Some(&self.nodes[index].value)
+  }
+
+  /// Returns the least recently used entry, without marking it as used.
+  pub fn peek_lru(&self) -> Option<(&K, &V)> {
+    let node = self.nodes.get(self.tail)?;
+    Some((&node.key, &node.value))
+  }
+
+  pub fn contains<Q>(&self, key: &Q) -> bool
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    self.find(key).is_some()
+  }
+
+  /// Inserts an entry as the most recently used, and returns what it
+  /// pushes out: the old value of `key` if it was there, or else the
+  /// least recently used entry if the cache was full.
+  pub fn put(&mut self, key: K, value: V) -> Option<Displaced<K, V>> {
+    if let Some(index) = self.find(&key) {
+      self.promote(index);
+      let old = mem::replace(&mut self.nodes[index].value, value);
+      return Some(Displaced::Replaced(old));
+    }
+    if self.cap == 0 {
+      return Some(Displaced::Evicted(key, value));
+    }
+
+    let hash = self.hash_builder.hash_one(&key);
+    let evicted = if self.len() == self.cap { self.pop_lru() } else { None };
+    let nodes = &self.nodes;
+    self.table.reserve(1, |&i| nodes[i].hash);
+    let index = self.nodes.len();
+    self.table.insert_no_grow(hash, index);
+    self.nodes.push(Node { hash, key, value, prev: NIL, next: NIL });
+    self.push_front(index);
+    evicted.map(|(key, value)| Displaced::Evicted(key, value))
+  }
+
+  /// Removes `key` and returns its value.
+  pub fn pop<Q>(&mut self, key: &Q) -> Option<V>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let index = self.find(key)?;
+    Some(self.remove_node(index).1)
+  }
+
+  /// Removes the least recently used entry and returns it.
+  pub fn pop_lru(&mut self) -> Option<(K, V)> {
+    match self.tail {
+      NIL => None,
+      tail => Some(self.remove_node(tail)),
+    }
+  }
+
+  /// Changes the capacity, evicting the least recently used entries if
+  /// there are more than `cap`.
+  pub fn resize(&mut self, cap: usize) {
+    while self.len() > cap {
+      self.pop_lru();
+    }
+    self.cap = cap;
+  }
+
+  /// Removes node `index` from the list, the table and `nodes`. The last
+  /// node takes its place, so the links and the table entry pointing at
+  /// that one are updated.
+  fn remove_node(&mut self, index: usize) -> (K, V) {
+    self.unlink(index);
+    let slot = self.table.find(self.nodes[index].hash, |&i| i == index).unwrap();
+    self.table.remove(slot);
+
+    let node = self.nodes.swap_remove(index);
+    let moved = self.nodes.len();
+    if index < moved {
+      let slot = self.table.find(self.nodes[index].hash, |&i| i == moved).unwrap();
+      *self.table.get_mut(slot) = index;
+      let (prev, next) = (self.nodes[index].prev, self.nodes[index].next);
+      match prev {
+        NIL => self.head = index,
+        prev => self.nodes[prev].next = index,
+      }
+      match next {
+        NIL => self.tail = index,
+        next => self.nodes[next].prev = index,
+      }
+    }
+    (node.key, node.value)
+  }
+}
+
+
+impl<K, V, S> fmt::Debug for LruCache<K, V, S>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+
+pub struct Iter<'a, K: 'a, V: 'a> {
+    nodes: &'a [Node<K, V>],
+    next: usize,
+}
+
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.nodes.get(self.next)?;
+        self.next = node.next;
+        Some((&node.key, &node.value))
+    }
+}
+
+
+impl<'a, K, V, S> IntoIterator for &'a LruCache<K, V, S> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rng::Rng;
+
+    fn keys(cache: &LruCache<i32, i32>) -> Vec<i32> {
+        cache.iter().map(|(&k, _)| k).collect()
+    }
+
+    fn filled(n: i32) -> LruCache<i32, i32> {
+        let mut cache = LruCache::new(n as usize);
+        for i in 0..n {
+            cache.put(i, i * 10);
+        }
+        cache
+    }
+
+    #[test]
+    fn put() {
+        let mut cache = filled(3);
+        assert_eq!(keys(&cache), [2, 1, 0]);
+        assert_eq!(cache.put(3, 30), Some(Displaced::Evicted(0, 0)));
+        assert_eq!(keys(&cache), [3, 2, 1]);
+        assert_eq!(cache.put(1, 11), Some(Displaced::Replaced(10)));
+        assert_eq!(keys(&cache), [1, 3, 2]);
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn get_promotes() {
+        let mut cache = filled(3);
+        assert_eq!(cache.get(&0), Some(&0));
+        assert_eq!(keys(&cache), [0, 2, 1]);
+        *cache.get_mut(&1).unwrap() += 1;
+        assert_eq!(keys(&cache), [1, 0, 2]);
+        assert_eq!(cache.put(3, 30), Some(Displaced::Evicted(2, 20)));
+        assert_eq!(cache.get(&2), None);
+    }
+
+    #[test]
+    fn peek() {
+        let cache = filled(3);
+        assert_eq!(cache.peek(&0), Some(&0));
+        assert_eq!(cache.peek_lru(), Some((&0, &0)));
+        assert_eq!(keys(&cache), [2, 1, 0]);
+    }
+
+    #[test]
+    fn pop() {
+        let mut cache = filled(4);
+        assert_eq!(cache.pop(&1), Some(10));
+        assert_eq!(cache.pop(&1), None);
+        assert_eq!(keys(&cache), [3, 2, 0]);
+        assert!(!cache.contains(&1));
+    }
+
+    #[test]
+    fn pop_lru() {
+        let mut cache = filled(3);
+        assert_eq!(cache.pop_lru(), Some((0, 0)));
+        assert_eq!(cache.pop_lru(), Some((1, 10)));
+        assert_eq!(cache.pop_lru(), Some((2, 20)));
+        assert_eq!(cache.pop_lru(), None);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn resize() {
+        let mut cache = filled(5);
+        cache.resize(2);
+        assert_eq!(keys(&cache), [4, 3]);
+        cache.resize(3);
+        assert_eq!(cache.put(5, 50), None);
+        assert_eq!(cache.put(6, 60), Some(Displaced::Evicted(3, 30)));
+        assert_eq!(cache.cap(), 3);
+    }
+
+    #[test]
+    fn presized() {
+        let mut cache = LruCache::new(100);
+        let (nbuckets, capacity) = (cache.table.nbuckets(), cache.nodes.capacity());
+        assert!(capacity >= 100);
+        for i in 0..200 {
+            cache.put(i, i);
+        }
+        assert_eq!(cache.table.nbuckets(), nbuckets);
+        assert_eq!(cache.nodes.capacity(), capacity);
+    }
+
+    #[test]
+    fn keys_need_not_be_clone() {
+        #[derive(Debug, PartialEq, Eq, Hash)]
+        struct Key(String);
+
+        let mut cache = LruCache::new(1);
+        assert_eq!(cache.put(Key("a".into()), 1), None);
+        assert_eq!(cache.put(Key("b".into()), 2), Some(Displaced::Evicted(Key("a".into()), 1)));
+        assert_eq!(cache.get(&Key("b".into())), Some(&2));
+    }
+
+    #[test]
+    fn zero_capacity() {
+        let mut cache = LruCache::new(0);
+        assert_eq!(cache.put(1, 1), Some(Displaced::Evicted(1, 1)));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn matches_model() {
+        // A vector ordered from most to least recently used is the model.
+        let mut cache = LruCache::new(16);
+        let mut model: Vec<(u32, u32)> = Vec::new();
+        let mut rng = Rng::new(1);
+        for step in 0..10_000 {
+            let key = rng.below(32) as u32;
+            match rng.below(3) {
+                0 => {
+                    let found = model.iter().position(|&(k, _)| k == key);
+                    let expected = found.map(|i| model.remove(i));
+                    if let Some(entry) = expected {
+                        model.insert(0, entry);
+                    }
+                    assert_eq!(cache.get(&key), expected.map(|(_, v)| v).as_ref());
+                }
+                1 => {
+                    let found = model.iter().position(|&(k, _)| k == key);
+                    let expected = match found {
+                        Some(i) => Some(Displaced::Replaced(model.remove(i).1)),
+                        None if model.len() == 16 => {
+                            model.pop().map(|(k, v)| Displaced::Evicted(k, v))
+                        }
+                        None => None,
+                    };
+                    model.insert(0, (key, step));
+                    assert_eq!(cache.put(key, step), expected);
+                }
+                _ => {
+                    let found = model.iter().position(|&(k, _)| k == key);
+                    assert_eq!(cache.pop(&key), found.map(|i| model.remove(i).1));
+                }
+            }
+            assert!(cache.iter().map(|(&k, &v)| (k, v)).eq(model.iter().copied()));
+        }
+    }
+
+    #[test]
+    fn debug() {
+        let cache = filled(2);
+        assert_eq!(format!("{:?}", cache), "{1: 10, 0: 0}");
+    }
+}
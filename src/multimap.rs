@@ -0,0 +1,394 @@
+//! A hash map holding any number of values per key.
+
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::iter::FromIterator;
+use std::mem;
+use std::slice;
+
+use crate::{Entry, HashMap};
+
+
+/// The values of one key, in insertion order.
+///
+/// A key with a single value, the common case, keeps it right in its
+/// bucket, so that only keys with several values allocate.
+#[derive(Clone)]
+enum Values<V> {
+    One(V),
+    /// Never fewer than two values.
+    Many(Vec<V>),
+}
+
+
+impl<V> Values<V> {
+    fn as_slice(&self) -> &[V] {
+        match self {
+            Values::One(value) => slice::from_ref(value),
+            Values::Many(values) => values,
+        }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [V] {
+        match self {
+            Values::One(value) => slice::from_mut(value),
+            Values::Many(values) => values,
+        }
+    }
+
+    fn push(&mut self, value: V) {
+        match self {
+            Values::Many(values) => values.push(value),
+            Values::One(_) => {
+                if let Values::One(first) = mem::replace(self, Values::Many(Vec::new())) {
+                    *self = Values::Many(vec![first, value]);
+                }
+            }
+        }
+    }
+
+    fn into_vec(self) -> Vec<V> {
+        match self {
+            Values::One(value) => vec![value],
+            Values::Many(values) => values,
+        }
+    }
+}
+
+
+/// A hash map from keys to any number of values, built on `HashMap`.
+///
+/// The values of a key are kept contiguously, in insertion order, and can
+/// be borrowed as one slice. Keys holding a single value need no
+/// allocation of their own.
+pub struct HashMultiMap<K, V, S = RandomState> {
+    map: HashMap<K, Values<V>, S>,
+    /// Number of values over all keys.
+    nvalues: usize,
+}
+
+
+impl<K, V> HashMultiMap<K, V, RandomState> {
+    pub fn new() -> Self {
+        HashMultiMap::with_hasher(RandomState::new())
+    }
+}
+
+
+impl<K, V, S> HashMultiMap<K, V, S> {
+    /// Creates an empty multimap which will use `hash_builder` to hash
+    /// keys.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        HashMultiMap { map: HashMap::with_hasher(hash_builder), nvalues: 0 }
+    }
+
+    /// Creates an empty multimap able to hold `capacity` keys without
+    /// resizing, which will use `hash_builder` to hash them.
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
+        HashMultiMap { map: HashMap::with_capacity_and_hasher(capacity, hash_builder), nvalues: 0 }
+    }
+
+    /// Returns a reference to the multimap's `BuildHasher`.
+    pub fn hasher(&self) -> &S {
+        self.map.hasher()
+    }
+
+    /// Visits each key along with all of its values.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { inner: self.map.iter() }
+    }
+
+    /// Removes every key and value.
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.nvalues = 0;
+    }
+}
+
+
+impl<K, V, S> HashMultiMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Appends `value` to the values of `key`.
+    pub fn insert(&mut self, key: K, value: V) {
+        match self.map.entry(key) {
+            Entry::Occupied(mut entry) => entry.get_mut().push(value),
+            Entry::Vacant(entry) => {
+                entry.insert(Values::One(value));
+            }
+        }
+        self.nvalues += 1;
+    }
+
+    /// Appends `values` to the values of `key`, for deserializing without
+    /// cloning the key. A key can't be in the map without values, so it is
+    /// left out if both are empty.
+    #[cfg(feature = "serde")]
+    pub(crate) fn insert_all(&mut self, key: K, mut values: Vec<V>) {
+        self.nvalues += values.len();
+        match self.map.entry(key) {
+            Entry::Occupied(mut entry) => {
+                for value in values {
+                    entry.get_mut().push(value);
+                }
+            }
+            Entry::Vacant(entry) => match values.len() {
+                0 => {}
+                1 => {
+                    entry.insert(Values::One(values.pop().unwrap()));
+                }
+                _ => {
+                    entry.insert(Values::Many(values));
+                }
+            },
+        }
+    }
+
+    /// Visits the values of `key`, in insertion order.
+    pub fn get_all<Q>(&self, key: &Q) -> slice::Iter<'_, V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get_slice(key).iter()
+    }
+
+    /// Returns the values of `key`, in insertion order.
+    pub fn get_slice<Q>(&self, key: &Q) -> &[V]
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get(key).map_or(&[], Values::as_slice)
+    }
+
+    /// Returns the values of `key`, in insertion order, for modification.
+    pub fn get_slice_mut<Q>(&mut self, key: &Q) -> &mut [V]
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get_mut(key).map_or(&mut [], Values::as_mut_slice)
+    }
+
+    /// Removes the first value of `key` equal to `value` and returns it.
+    /// The key goes away along with its last value.
+    pub fn remove_one<Q>(&mut self, key: &Q, value: &V) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: PartialEq,
+    {
+        let values = self.map.get_mut(key)?;
+        let index = values.as_slice().iter().position(|v| v == value)?;
+        let removed = match values {
+            Values::Many(many) if many.len() > 2 => many.remove(index),
+            Values::Many(many) => {
+                let removed = many.remove(index);
+                *values = Values::One(many.pop().unwrap());
+                removed
+            }
+            Values::One(_) => self.map.remove(key).unwrap().into_vec().pop().unwrap(),
+        };
+        self.nvalues -= 1;
+        Some(removed)
+    }
+
+    /// Removes `key` and returns all of its values, in insertion order.
+    pub fn remove_all<Q>(&mut self, key: &Q) -> Vec<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let values = self.map.remove(key).map_or_else(Vec::new, Values::into_vec);
+        self.nvalues -= values.len();
+        values
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    /// Number of distinct keys.
+    pub fn len_keys(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Number of values over all keys.
+    pub fn len_values(&self) -> usize {
+        self.nvalues
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+
+impl<K, V, S: Default> Default for HashMultiMap<K, V, S> {
+    fn default() -> Self {
+        HashMultiMap::with_hasher(S::default())
+    }
+}
+
+
+impl<K, V, S> Clone for HashMultiMap<K, V, S>
+where
+    K: Clone,
+    V: Clone,
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        HashMultiMap { map: self.map.clone(), nvalues: self.nvalues }
+    }
+}
+
+
+impl<K, V, S> fmt::Debug for HashMultiMap<K, V, S>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+
+pub struct Iter<'a, K: 'a, V: 'a> {
+    inner: crate::Iter<'a, K, Values<V>>,
+}
+
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a [V]);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(key, values)| (key, values.as_slice()))
+    }
+}
+
+
+impl<'a, K, V, S> IntoIterator for &'a HashMultiMap<K, V, S> {
+    type Item = (&'a K, &'a [V]);
+    type IntoIter = Iter<'a, K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+
+impl<K, V, S> FromIterator<(K, V)> for HashMultiMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher + Default,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = HashMultiMap::with_hasher(S::default());
+        map.extend(iter);
+        map
+    }
+}
+
+
+impl<K, V, S> Extend<(K, V)> for HashMultiMap<K, V, S>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert() {
+        let mut map = HashMultiMap::new();
+        map.insert("a", 1);
+        map.insert("a", 2);
+        map.insert("b", 3);
+        assert_eq!(map.len_keys(), 2);
+        assert_eq!(map.len_values(), 3);
+        assert_eq!(map.get_slice("a"), [1, 2]);
+    }
+
+    #[test]
+    fn get_all() {
+        let map: HashMultiMap<_, _> = vec![("a", 1), ("b", 2), ("a", 3)].into_iter().collect();
+        assert_eq!(map.get_all("a").copied().collect::<Vec<_>>(), [1, 3]);
+        assert_eq!(map.get_all("c").count(), 0);
+    }
+
+    #[test]
+    fn get_slice_mut() {
+        let mut map = HashMultiMap::new();
+        map.insert("a", 1);
+        map.insert("a", 2);
+        for value in map.get_slice_mut("a") {
+            *value *= 10;
+        }
+        assert_eq!(map.get_slice("a"), [10, 20]);
+        assert!(map.get_slice_mut("b").is_empty());
+    }
+
+    #[test]
+    fn remove_one() {
+        let mut map: HashMultiMap<_, _> = vec![("a", 1), ("a", 2), ("a", 1)].into_iter().collect();
+        assert_eq!(map.remove_one("a", &1), Some(1));
+        assert_eq!(map.get_slice("a"), [2, 1]);
+        assert_eq!(map.remove_one("a", &3), None);
+        assert_eq!(map.remove_one("a", &2), Some(2));
+        assert_eq!(map.get_slice("a"), [1]);
+        assert_eq!(map.remove_one("a", &1), Some(1));
+        assert!(!map.contains_key("a"));
+        assert_eq!(map.len_values(), 0);
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn remove_all() {
+        let mut map: HashMultiMap<_, _> = vec![("a", 1), ("b", 2), ("a", 3)].into_iter().collect();
+        assert_eq!(map.remove_all("a"), [1, 3]);
+        assert_eq!(map.remove_all("a"), []);
+        assert_eq!(map.len_keys(), 1);
+        assert_eq!(map.len_values(), 1);
+    }
+
+    #[test]
+    fn iter() {
+        let map: HashMultiMap<_, _> = vec![("a", 1), ("b", 2), ("a", 3)].into_iter().collect();
+        let mut entries: Vec<_> = map.iter().map(|(&k, v)| (k, v.to_vec())).collect();
+        entries.sort();
+        assert_eq!(entries, [("a", vec![1, 3]), ("b", vec![2])]);
+    }
+
+    #[test]
+    fn clear() {
+        let mut map: HashMultiMap<_, _> = vec![("a", 1), ("a", 2)].into_iter().collect();
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.len_values(), 0);
+    }
+
+    #[test]
+    fn debug() {
+        let mut map = HashMultiMap::new();
+        map.insert("a", 1);
+        map.insert("a", 2);
+        assert_eq!(format!("{:?}", map), r#"{"a": [1, 2]}"#);
+    }
+}
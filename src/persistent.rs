@@ -0,0 +1,690 @@
+//! An immutable hash map whose versions share structure, as a hash array
+//! mapped trie.
+//!
+//! Each level of the trie picks a child by the next `BITS` bits of the
+//! key's hash, mixed and taken from the top like `HashMap` does, and only
+//! stores the children present, listed by a bitmap. Modifying a map copies
+//! the nodes on the path to the key, and shares all others with the
+//! original.
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::iter::FromIterator;
+use std::mem;
+use std::slice;
+use std::sync::Arc;
+
+use crate::raw;
+
+
+/// Number of hash bits consumed per level.
+const BITS: u32 = 5;
+
+
+#[derive(Clone)]
+enum Node<K, V> {
+    /// Children by the hash bits of this level, present ones in the order of
+    /// `bitmap`.
+    Branch { bitmap: u32, children: Vec<Child<K, V>> },
+    /// Two or more entries whose hashes are equal in all 64 bits.
+    Collision { hash: u64, entries: Vec<(K, V)> },
+}
+
+
+#[derive(Clone)]
+enum Child<K, V> {
+    Entry { hash: u64, key: K, value: V },
+    Node(Arc<Node<K, V>>),
+}
+
+
+/// The bits of `hash` picking a child at the level starting at bit `shift`
+/// from the top.
+fn index(hash: u64, shift: u32) -> u32 {
+    ((hash << shift) >> (64 - BITS)) as u32
+}
+
+
+impl<K, V> Node<K, V> {
+    fn empty() -> Self {
+        Node::Branch { bitmap: 0, children: Vec::new() }
+    }
+
+    /// The node holding just two entries, whose hashes agree on the levels
+    /// above `shift`.
+    fn pair(shift: u32, a: Child<K, V>, b: Child<K, V>) -> Self {
+        let (hash_a, hash_b) = match (&a, &b) {
+            (Child::Entry { hash: ha, .. }, Child::Entry { hash: hb, .. }) => (*ha, *hb),
+            _ => unreachable!("pair of nodes"),
+        };
+        if shift >= 64 {
+            let entries = vec![a, b].into_iter().map(|child| match child {
+                Child::Entry { key, value, .. } => (key, value),
+                Child::Node(_) => unreachable!(),
+            });
+            return Node::Collision { hash: hash_a, entries: entries.collect() };
+        }
+        let (ia, ib) = (index(hash_a, shift), index(hash_b, shift));
+        let children = match ia.cmp(&ib) {
+            Ordering::Equal => vec![Child::Node(Arc::new(Node::pair(shift + BITS, a, b)))],
+            Ordering::Less => vec![a, b],
+            Ordering::Greater => vec![b, a],
+        };
+        Node::Branch { bitmap: 1 << ia | 1 << ib, children }
+    }
+
+    fn get<Q>(&self, hash: u64, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        let mut node = self;
+        let mut shift = 0;
+        loop {
+            match node {
+                Node::Branch { bitmap, children } => {
+                    let bit = 1 << index(hash, shift);
+                    if bitmap & bit == 0 {
+                        return None;
+                    }
+                    match &children[(bitmap & (bit - 1)).count_ones() as usize] {
+                        Child::Entry { hash: h, key: k, value } => {
+                            return if *h == hash && k.borrow() == key { Some((k, value)) } else { None };
+                        }
+                        Child::Node(child) => node = child,
+                    }
+                    shift += BITS;
+                }
+                Node::Collision { entries, .. } => {
+                    return entries.iter().find(|(k, _)| k.borrow() == key).map(|(k, v)| (k, v));
+                }
+            }
+        }
+    }
+}
+
+
+impl<K: Clone + Eq, V: Clone> Node<K, V> {
+    /// Inserts below `node`, at the level starting at bit `shift`, copying
+    /// the nodes shared with other maps on the way.
+    fn insert(node: &mut Arc<Self>, shift: u32, hash: u64, key: K, value: V) -> Option<V> {
+        match Arc::make_mut(node) {
+            Node::Branch { bitmap, children } => {
+                let bit = 1 << index(hash, shift);
+                let pos = (*bitmap & (bit - 1)).count_ones() as usize;
+                if *bitmap & bit == 0 {
+                    *bitmap |= bit;
+                    children.insert(pos, Child::Entry { hash, key, value });
+                    return None;
+                }
+                match &mut children[pos] {
+                    Child::Node(child) => Node::insert(child, shift + BITS, hash, key, value),
+                    Child::Entry { hash: h, key: k, value: v } if *h == hash && *k == key => {
+                        Some(mem::replace(v, value))
+                    }
+                    slot => {
+                        let old = mem::replace(slot, Child::Node(Arc::new(Node::empty())));
+                        let new = Child::Entry { hash, key, value };
+                        *slot = Child::Node(Arc::new(Node::pair(shift + BITS, old, new)));
+                        None
+                    }
+                }
+            }
+            Node::Collision { entries, .. } => match entries.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, v)) => Some(mem::replace(v, value)),
+                None => {
+                    entries.push((key, value));
+                    None
+                }
+            },
+        }
+    }
+
+    /// Removes `key` from below `node`, which must hold it, copying the
+    /// nodes shared with other maps on the way.
+    fn remove<Q>(node: &mut Arc<Self>, shift: u32, hash: u64, key: &Q) -> (K, V)
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        match Arc::make_mut(node) {
+            Node::Branch { bitmap, children } => {
+                let bit = 1 << index(hash, shift);
+                let pos = (*bitmap & (bit - 1)).count_ones() as usize;
+                let child = match &mut children[pos] {
+                    Child::Node(child) => child,
+                    Child::Entry { .. } => {
+                        *bitmap &= !bit;
+                        match children.remove(pos) {
+                            Child::Entry { key, value, .. } => return (key, value),
+                            Child::Node(_) => unreachable!(),
+                        }
+                    }
+                };
+                let removed = Node::remove(child, shift + BITS, hash, key);
+                // Keep every node holding two entries or more, so that
+                // lookups don't walk down chains of single children.
+                if let Some(entry) = Node::take_lone_entry(child) {
+                    children[pos] = entry;
+                }
+                removed
+            }
+            Node::Collision { entries, .. } => {
+                let pos = entries.iter().position(|(k, _)| k.borrow() == key).unwrap();
+                entries.swap_remove(pos)
+            }
+        }
+    }
+
+    /// Takes the entry out of `node` if it is the only one left below it.
+    fn take_lone_entry(node: &mut Arc<Self>) -> Option<Child<K, V>> {
+        match Arc::make_mut(node) {
+            Node::Branch { children, .. } if children.len() == 1 => match children[0] {
+                Child::Entry { .. } => children.pop(),
+                Child::Node(_) => None,
+            },
+            Node::Collision { hash, entries } if entries.len() == 1 => {
+                let (key, value) = entries.pop().unwrap();
+                Some(Child::Entry { hash: *hash, key, value })
+            }
+            _ => None,
+        }
+    }
+}
+
+
+/// An immutable hash map, modified by making new versions which share most
+/// of their memory with the old one.
+///
+/// Cloning is O(1), and `insert` and `remove` return a new map in
+/// O(log n) time and memory, leaving the original untouched. For many
+/// changes in a row, `transient` gives a builder which edits the map in
+/// place, only copying what other versions still share.
+pub struct PersistentHashMap<K, V, S = RandomState> {
+    /// Always a branch.
+    root: Arc<Node<K, V>>,
+    len: usize,
+    hash_builder: S,
+}
+
+
+impl<K, V> PersistentHashMap<K, V, RandomState> {
+    pub fn new() -> Self {
+        PersistentHashMap::with_hasher(RandomState::new())
+    }
+}
+
+
+impl<K, V, S> PersistentHashMap<K, V, S> {
+    /// Creates an empty map which will use `hash_builder` to hash keys.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        PersistentHashMap { root: Arc::new(Node::empty()), len: 0, hash_builder }
+    }
+
+    /// Returns a reference to the map's `BuildHasher`.
+    pub fn hasher(&self) -> &S {
+        &self.hash_builder
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether both maps are versions sharing all of their entries, which
+    /// makes them equal.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.root, &other.root)
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let children = match &*self.root {
+            Node::Branch { children, .. } => children.iter(),
+            Node::Collision { .. } => unreachable!("collision at the root"),
+        };
+        Iter { stack: vec![children], collision: [].iter(), remaining: self.len }
+    }
+
+    /// Returns a builder to edit a version of this map in place.
+    pub fn transient(&self) -> Transient<K, V, S>
+    where
+        S: Clone,
+    {
+        Transient { map: self.clone() }
+    }
+}
+
+
+impl<K, V, S> PersistentHashMap<K, V, S>
+where
+  K: Hash + Eq + Clone,
+  V: Clone,
+  S: BuildHasher + Clone,
+{
+  fn hash<Q>(&self, key: &Q) -> u64
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    raw::mix(self.hash_builder.hash_one(key))
+  }
+
+  pub fn get<Q>(&self, key: &Q) -> Option<&V>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    self.get_key_value(key).map(|(_, v)| v)
+  }
+
+  pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    self.root.get(self.hash(key), key)
+  }
+
+  pub fn contains_key<Q>(&self, key: &Q) -> bool
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    self.get(key).is_some()
+  }
+
+  /// Returns a new map with `value` under `key`.
+  pub fn insert(&self, key: K, value: V) -> Self {
+    let mut map = self.clone();
+    map.insert_mut(key, value);
+    map
+  }
+
+  /// Returns a new map without `key`.
+  pub fn remove<Q>(&self, key: &Q) -> Self
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let mut map = self.clone();
+    map.remove_mut(key);
+    map
+  }
+
+  fn insert_mut(&mut self, key: K, value: V) -> Option<V> {
+    let hash = self.hash(&key);
+    let old = Node::insert(&mut self.root, 0, hash, key, value);
+    if old.is_none() {
+      self.len += 1;
+    }
+    old
+  }
+
+  fn remove_mut<Q>(&mut self, key: &Q) -> Option<(K, V)>
+  where
+    K: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+  {
+    let hash = self.hash(key);
+    // Don't copy a path only to find the key isn't there.
+    self.root.get(hash, key)?;
+    self.len -= 1;
+    Some(Node::remove(&mut self.root, 0, hash, key))
+  }
+}
+
+
+impl<K, V, S: Clone> Clone for PersistentHashMap<K, V, S> {
+    fn clone(&self) -> Self {
+        PersistentHashMap { root: Arc::clone(&self.root), len: self.len, hash_builder: self.hash_builder.clone() }
+    }
+}
+
+
+impl<K, V, S: Default> Default for PersistentHashMap<K, V, S> {
+    fn default() -> Self {
+        PersistentHashMap::with_hasher(S::default())
+    }
+}
+
+
+impl<K, V, S> PartialEq for PersistentHashMap<K, V, S>
+where
+    K: Hash + Eq + Clone,
+    V: PartialEq + Clone,
+    S: BuildHasher + Clone,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other)
+            || self.len() == other.len() && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+
+impl<K, V, S> Eq for PersistentHashMap<K, V, S>
+where
+    K: Hash + Eq + Clone,
+    V: Eq + Clone,
+    S: BuildHasher + Clone,
+{
+}
+
+
+impl<K, V, S> fmt::Debug for PersistentHashMap<K, V, S>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+
+impl<K, V, S> FromIterator<(K, V)> for PersistentHashMap<K, V, S>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    S: BuildHasher + Clone + Default,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Transient { map: PersistentHashMap::default() };
+        map.extend(iter);
+        map.persistent()
+    }
+}
+
+
+/// A `PersistentHashMap` being edited in place, for bulk changes.
+///
+/// Nodes it shares with other versions are copied on first change, after
+/// which further changes below them need no copying.
+pub struct Transient<K, V, S = RandomState> {
+    map: PersistentHashMap<K, V, S>,
+}
+
+
+impl<K, V, S> Transient<K, V, S>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.map.insert_mut(key, value)
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.remove_mut(key).map(|(_, v)| v)
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Turns the edited map back into a persistent one.
+    pub fn persistent(self) -> PersistentHashMap<K, V, S> {
+        self.map
+    }
+}
+
+
+impl<K, V, S> Extend<(K, V)> for Transient<K, V, S>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+
+pub struct Iter<'a, K: 'a, V: 'a> {
+    /// Children left to visit at each level down to the current one.
+    stack: Vec<slice::Iter<'a, Child<K, V>>>,
+    /// Entries left in the collision node being visited.
+    collision: slice::Iter<'a, (K, V)>,
+    remaining: usize,
+}
+
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((key, value)) = self.collision.next() {
+                self.remaining -= 1;
+                return Some((key, value));
+            }
+            match self.stack.last_mut()?.next() {
+                None => {
+                    self.stack.pop();
+                }
+                Some(Child::Entry { key, value, .. }) => {
+                    self.remaining -= 1;
+                    return Some((key, value));
+                }
+                Some(Child::Node(node)) => match &**node {
+                    Node::Branch { children, .. } => self.stack.push(children.iter()),
+                    Node::Collision { entries, .. } => self.collision = entries.iter(),
+                },
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+
+impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V> {}
+
+
+impl<'a, K, V, S> IntoIterator for &'a PersistentHashMap<K, V, S> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_rng::Rng;
+    use std::hash::{BuildHasherDefault, Hasher};
+
+    /// Gives every key the same hash.
+    #[derive(Default)]
+    struct Collide;
+    impl Hasher for Collide {
+        fn finish(&self) -> u64 { 0 }
+        fn write(&mut self, _: &[u8]) {}
+    }
+
+    /// The depth of the deepest node.
+    fn depth<K, V>(node: &Node<K, V>) -> usize {
+        match node {
+            Node::Branch { children, .. } => {
+                let nodes = children.iter().filter_map(|child| match child {
+                    Child::Node(node) => Some(depth(node)),
+                    Child::Entry { .. } => None,
+                });
+                1 + nodes.max().unwrap_or(0)
+            }
+            Node::Collision { .. } => 1,
+        }
+    }
+
+    #[test]
+    fn insert() {
+        let empty = PersistentHashMap::new();
+        let one = empty.insert("a", 1);
+        let two = one.insert("b", 2).insert("a", 3);
+        assert!(empty.is_empty());
+        assert_eq!(one.len(), 1);
+        assert_eq!(one.get("a"), Some(&1));
+        assert_eq!(two.len(), 2);
+        assert_eq!(two.get("a"), Some(&3));
+        assert_eq!(two.get("b"), Some(&2));
+        assert_eq!(two.get("c"), None);
+    }
+
+    #[test]
+    fn remove() {
+        let map: PersistentHashMap<_, _> = (0..100).map(|i| (i, i)).collect();
+        let removed = map.remove(&10).remove(&20);
+        assert_eq!(map.len(), 100);
+        assert_eq!(map.get(&10), Some(&10));
+        assert_eq!(removed.len(), 98);
+        assert!(!removed.contains_key(&10));
+        assert!(removed.remove(&1000).ptr_eq(&removed));
+    }
+
+    #[test]
+    fn remove_compacts() {
+        let mut map: PersistentHashMap<_, _> = (0..1000).map(|i| (i, i)).collect();
+        assert!(depth(&map.root) > 2);
+        for i in 2..1000 {
+            map = map.remove(&i);
+        }
+        // Whatever their hashes, two entries in different top-level slots
+        // need no nodes below the root.
+        if index(map.hash(&0), 0) != index(map.hash(&1), 0) {
+            assert_eq!(depth(&map.root), 1);
+        }
+        map = map.remove(&0).remove(&1);
+        assert_eq!(depth(&map.root), 1);
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn shares_structure() {
+        let map: PersistentHashMap<_, _> = (0..1000).map(|i| (i, i)).collect();
+        let other = map.insert(0, 1);
+        let children = |map: &PersistentHashMap<i32, i32>| match &*map.root {
+            Node::Branch { children, .. } => children.clone(),
+            Node::Collision { .. } => unreachable!(),
+        };
+        let shared = children(&map).iter().zip(children(&other).iter()).filter(|(a, b)| match (a, b) {
+            (Child::Node(a), Child::Node(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }).count();
+        assert_eq!(shared, 31);
+    }
+
+    #[test]
+    fn clone() {
+        let map: PersistentHashMap<_, _> = (0..100).map(|i| (i, i)).collect();
+        let copy = map.clone();
+        assert!(copy.ptr_eq(&map));
+        assert_eq!(copy, map);
+    }
+
+    #[test]
+    fn transient() {
+        let map: PersistentHashMap<_, _> = (0..100).map(|i| (i, i)).collect();
+        let mut builder = map.transient();
+        for i in 0..50 {
+            assert_eq!(builder.remove(&i), Some(i));
+        }
+        assert_eq!(builder.insert(50, 0), Some(50));
+        assert_eq!(builder.insert(100, 100), None);
+        assert_eq!(builder.len(), 51);
+        let edited = builder.persistent();
+        assert_eq!(map.len(), 100);
+        assert_eq!(map.get(&50), Some(&50));
+        assert_eq!(edited.get(&50), Some(&0));
+        assert_eq!(edited.get(&100), Some(&100));
+        assert!(!edited.contains_key(&0));
+    }
+
+    #[test]
+    fn collisions() {
+        let mut map = PersistentHashMap::with_hasher(BuildHasherDefault::<Collide>::default());
+        for i in 0..20 {
+            map = map.insert(i, i);
+        }
+        assert_eq!(map.len(), 20);
+        assert_eq!(map.iter().count(), 20);
+        for i in 0..20 {
+            assert_eq!(map.get(&i), Some(&i));
+        }
+        for i in 0..19 {
+            map = map.remove(&i);
+        }
+        assert_eq!(map.get(&19), Some(&19));
+        assert_eq!(depth(&map.root), 1);
+    }
+
+    #[test]
+    fn iter() {
+        let map: PersistentHashMap<_, _> = (0..1000).map(|i| (i, i * 2)).collect();
+        assert_eq!(map.iter().len(), 1000);
+        let mut entries: Vec<_> = map.iter().map(|(&k, &v)| (k, v)).collect();
+        entries.sort();
+        assert_eq!(entries, (0..1000).map(|i| (i, i * 2)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn matches_model() {
+        let mut map = PersistentHashMap::new();
+        let mut model = std::collections::HashMap::new();
+        let mut versions = Vec::new();
+        let mut rng = Rng::new(1);
+        for _ in 0..5000 {
+            let (key, value) = (rng.below(256) as u32, rng.next() as u32);
+            if rng.below(3) == 0 {
+                map = map.remove(&key);
+                model.remove(&key);
+            } else {
+                map = map.insert(key, value);
+                model.insert(key, value);
+            }
+            assert_eq!(map.len(), model.len());
+            if rng.below(50) == 0 {
+                versions.push((map.clone(), model.clone()));
+            }
+        }
+        for (map, model) in versions {
+            assert_eq!(map.len(), model.len());
+            for (key, value) in &model {
+                assert_eq!(map.get(key), Some(value));
+            }
+        }
+    }
+
+    #[test]
+    fn debug() {
+        let map = PersistentHashMap::new().insert("a", 1);
+        assert_eq!(format!("{:?}", map), r#"{"a": 1}"#);
+    }
+}
@@ -0,0 +1,166 @@
+//! When the table behind a `HashMap` grows and shrinks.
+
+
+/// How full a map's table gets before it grows, by how much it grows, and
+/// whether it shrinks again after removals.
+///
+/// Bucket counts are always powers of two, so sizes computed from the
+/// policy are rounded up to the next one. The default matches std: a
+/// maximum load factor of 3/4, doubling, and no shrinking.
+///
+/// ```
+/// use hashmap::{GrowthPolicy, HashMap};
+///
+/// let mut map = HashMap::new();
+/// map.set_growth_policy(GrowthPolicy {
+///     max_load_factor: 0.5,
+///     ..GrowthPolicy::default()
+/// });
+/// map.insert(1, "a");
+/// assert_eq!(map.capacity(), 2);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GrowthPolicy {
+    /// The fraction of the buckets which may hold entries before the table
+    /// grows. Must be in `(0, 1)`: SwissTable probing needs some buckets
+    /// to stay empty.
+    pub max_load_factor: f64,
+    /// How many times bigger the table gets when it grows. Must be more
+    /// than 1.
+    pub growth_multiplier: f64,
+    /// If set, `remove` shrinks the table once the fraction of buckets
+    /// holding entries falls below this, leaving room for the entries to
+    /// grow by `growth_multiplier` again. Must be below `max_load_factor`.
+    pub shrink_threshold: Option<f64>,
+    /// The fewest buckets an allocated table has.
+    pub min_buckets: usize,
+}
+
+
+impl Default for GrowthPolicy {
+    fn default() -> Self {
+        GrowthPolicy {
+            max_load_factor: 0.75,
+            growth_multiplier: 2.0,
+            shrink_threshold: None,
+            min_buckets: 4,
+        }
+    }
+}
+
+
+impl GrowthPolicy {
+    /// Panics if the policy makes no sense.
+    pub(crate) fn validate(&self) {
+        assert!(
+            self.max_load_factor > 0.0 && self.max_load_factor < 1.0,
+            "max_load_factor must be in (0, 1), got {}",
+            self.max_load_factor
+        );
+        assert!(
+            self.growth_multiplier > 1.0,
+            "growth_multiplier must be more than 1, got {}",
+            self.growth_multiplier
+        );
+        if let Some(threshold) = self.shrink_threshold {
+            assert!(
+                threshold >= 0.0 && threshold < self.max_load_factor,
+                "shrink_threshold must be in [0, max_load_factor), got {}",
+                threshold
+            );
+        }
+    }
+
+    /// Number of entries a table of `nbuckets` buckets holds.
+    pub(crate) fn capacity_for(&self, nbuckets: usize) -> usize {
+        match nbuckets {
+            0 => 0,
+            // Keep at least one bucket empty whatever the load factor.
+            n => ((n as f64 * self.max_load_factor) as usize).min(n - 1),
+        }
+    }
+
+    /// Number of buckets needed to hold `items` entries without resizing.
+    pub(crate) fn buckets_for(&self, items: usize) -> usize {
+        if items == 0 {
+            return 0;
+        }
+        let mut nbuckets = self.min_buckets.max(1).next_power_of_two();
+        while self.capacity_for(nbuckets) < items {
+            nbuckets = nbuckets.checked_mul(2).expect("capacity overflow");
+        }
+        nbuckets
+    }
+
+    /// Number of buckets a table of `nbuckets` buckets grows to.
+    pub(crate) fn grown(&self, nbuckets: usize) -> usize {
+        let grown = (nbuckets as f64 * self.growth_multiplier).ceil();
+        // The cast saturates, which `checked_next_power_of_two` then rejects.
+        (grown as usize).checked_next_power_of_two().expect("capacity overflow")
+    }
+
+    /// Whether a table of `nbuckets` buckets holding `items` entries is
+    /// sparse enough to shrink.
+    pub(crate) fn should_shrink(&self, items: usize, nbuckets: usize) -> bool {
+        match self.shrink_threshold {
+            Some(threshold) => (items as f64) < threshold * nbuckets as f64,
+            None => false,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::GrowthPolicy;
+
+    #[test]
+    fn buckets_for() {
+        let policy = GrowthPolicy::default();
+        assert_eq!(policy.buckets_for(0), 0);
+        assert_eq!(policy.buckets_for(1), 4);
+        assert_eq!(policy.buckets_for(3), 4);
+        assert_eq!(policy.buckets_for(4), 8);
+        assert_eq!(policy.buckets_for(6), 8);
+        assert_eq!(policy.buckets_for(7), 16);
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity overflow")]
+    fn grown_overflow() {
+        GrowthPolicy::default().grown(1 << (usize::BITS - 1));
+    }
+
+    #[test]
+    fn load_factor() {
+        let dense = GrowthPolicy { max_load_factor: 0.95, ..GrowthPolicy::default() };
+        assert_eq!(dense.capacity_for(4), 3);
+        assert_eq!(dense.capacity_for(64), 60);
+        assert_eq!(dense.buckets_for(60), 64);
+
+        let sparse = GrowthPolicy { max_load_factor: 0.5, ..GrowthPolicy::default() };
+        assert_eq!(sparse.capacity_for(64), 32);
+        assert_eq!(sparse.buckets_for(33), 128);
+    }
+
+    #[test]
+    fn min_buckets() {
+        let policy = GrowthPolicy { min_buckets: 100, ..GrowthPolicy::default() };
+        assert_eq!(policy.buckets_for(0), 0);
+        assert_eq!(policy.buckets_for(1), 128);
+    }
+
+    #[test]
+    fn growth_multiplier() {
+        let policy = GrowthPolicy { growth_multiplier: 1.5, ..GrowthPolicy::default() };
+        assert_eq!(policy.grown(16), 32);
+        let policy = GrowthPolicy { growth_multiplier: 4.0, ..GrowthPolicy::default() };
+        assert_eq!(policy.grown(16), 64);
+    }
+
+    #[test]
+    #[should_panic(expected = "max_load_factor")]
+    fn full_load_factor() {
+        GrowthPolicy { max_load_factor: 1.0, ..GrowthPolicy::default() }.validate();
+    }
+}
@@ -0,0 +1,942 @@
+//! The open-addressing table behind `HashMap`, probed like Abseil's
+//! SwissTable.
+//!
+//! Values live in a vector of buckets, and every bucket has a control byte:
+//! `EMPTY`, `DELETED` (a tombstone), or 7 bits of the hash of its value.
+//! Buckets are plain `Option<T>`s, which may take more room than `T` for
+//! the discriminant, rather than uninitialized memory tracked by the
+//! control bytes alone as in SwissTable; this keeps bucket accesses free
+//! of unsafe code. Lookups compare a whole group of control bytes against
+//! those 7 bits at once, with SSE2 on x86_64 and with word-sized bit
+//! tricks elsewhere, so only buckets whose control byte matches are ever
+//! compared by key. Groups are probed in a triangular sequence until one
+//! of them has an `EMPTY` byte.
+//!
+//! Hashes are first mixed by Fibonacci hashing, and both the control byte
+//! and the bucket where probing starts come from the top bits of the
+//! result, so bucket counts are powers of two and no division is needed.
+//!
+//! Removing a value leaves a tombstone when a probe may have passed over
+//! its bucket. Tombstones are reused by inserts, and once they take up
+//! enough of the table it is rehashed in place rather than grown.
+//!
+//! The table knows nothing about keys: callers hash them, pass an equality
+//! predicate to `find`, and pass a hasher to anything which may move
+//! values between buckets. Tables can also cache the full hash of each
+//! value, in which case that hasher is only needed to fill the cache.
+
+use std::mem;
+use std::slice;
+use std::vec;
+
+use crate::policy::GrowthPolicy;
+
+#[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+mod sse2;
+#[cfg(any(test, not(all(target_arch = "x86_64", target_feature = "sse2"))))]
+mod generic;
+
+#[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+use self::sse2::Group;
+#[cfg(not(all(target_arch = "x86_64", target_feature = "sse2")))]
+use self::generic::Group;
+
+
+/// Control byte of a bucket which has never held a value since the last
+/// rehash.
+const EMPTY: u8 = 0b1111_1111;
+/// Control byte of a bucket whose value was removed.
+const DELETED: u8 = 0b1000_0000;
+
+
+/// Whether a control byte belongs to a bucket holding a value.
+fn is_full(ctrl: u8) -> bool {
+    ctrl & 0x80 == 0
+}
+
+
+/// 2^64 divided by the golden ratio, rounded to an odd number.
+pub(crate) const FIBONACCI: u64 = 0x9E37_79B9_7F4A_7C15;
+
+
+/// Scrambles `hash` so that every one of its bits affects the top bits of
+/// the result, which are the ones the table uses. A multiplication only
+/// carries bits upwards, so the high half is first folded into the low
+/// half: otherwise the top 7 bits of `hash` would only reach `h2`, and
+/// hashes differing only there would all start probing at one bucket.
+pub(crate) fn mix(hash: u64) -> u64 {
+    (hash ^ hash >> 32).wrapping_mul(FIBONACCI)
+}
+
+
+/// The bucket where probing for `hash` starts, in a table of `nbuckets`
+/// buckets: the `log2(nbuckets)` bits of the mixed hash right below those
+/// used by `h2`.
+fn h1(hash: u64, nbuckets: usize) -> usize {
+    let shift = 64 - nbuckets.trailing_zeros();
+    (mix(hash) << 7).checked_shr(shift).unwrap_or(0) as usize
+}
+
+
+/// The top 7 bits of the mixed hash, stored in the control byte.
+fn h2(hash: u64) -> u8 {
+    (mix(hash) >> 57) as u8
+}
+
+
+/// The result of matching a group: one bit per control byte, lowest first.
+#[derive(Clone, Copy)]
+pub(crate) struct BitMask(u16);
+
+
+impl BitMask {
+    fn any_bit_set(self) -> bool {
+        self.0 != 0
+    }
+
+    fn lowest_set_bit(self) -> Option<usize> {
+        match self.0 {
+            0 => None,
+            bits => Some(bits.trailing_zeros() as usize),
+        }
+    }
+
+    fn trailing_zeros(self) -> usize {
+        self.0.trailing_zeros() as usize
+    }
+
+    /// Leading zeros within the group's `Group::WIDTH` bits.
+    fn leading_zeros(self) -> usize {
+        self.0.leading_zeros() as usize - (16 - Group::WIDTH)
+    }
+}
+
+
+impl Iterator for BitMask {
+    type Item = usize;
+    fn next(&mut self) -> Option<usize> {
+        let bit = self.lowest_set_bit()?;
+        self.0 &= self.0 - 1;
+        Some(bit)
+    }
+}
+
+
+/// Group positions visited when looking for a hash: the stride grows by one
+/// group each step, which visits every group of a power-of-two table.
+struct ProbeSeq {
+    pos: usize,
+    stride: usize,
+}
+
+
+impl ProbeSeq {
+    fn move_next(&mut self, mask: usize) {
+        self.stride += Group::WIDTH;
+        self.pos = (self.pos + self.stride) & mask;
+    }
+}
+
+
+pub(crate) struct RawTable<T> {
+    /// One control byte per bucket, followed by a copy of the first
+    /// `Group::WIDTH` of them so that a group can be loaded from any
+    /// bucket without wrapping. Tables smaller than a group pad the gap
+    /// with `EMPTY`.
+    ctrl: Vec<u8>,
+    buckets: Vec<Option<T>>,
+    items: usize,
+    /// Inserts left before the table must grow or be rehashed. Tombstones
+    /// count against it until a rehash clears them.
+    growth_left: usize,
+    policy: GrowthPolicy,
+    /// The full hash of the value in each bucket if `cache_hashes` is set,
+    /// and empty otherwise.
+    hashes: Vec<u64>,
+    cache_hashes: bool,
+}
+
+
+impl<T> RawTable<T> {
+    pub(crate) fn new() -> Self {
+        RawTable {
+            ctrl: Vec::new(),
+            buckets: Vec::new(),
+            items: 0,
+            growth_left: 0,
+            policy: GrowthPolicy::default(),
+            hashes: Vec::new(),
+            cache_hashes: false,
+        }
+    }
+
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        let policy = GrowthPolicy::default();
+        RawTable::with_buckets(policy.buckets_for(capacity), policy, false)
+    }
+
+    fn with_buckets(nbuckets: usize, policy: GrowthPolicy, cache_hashes: bool) -> Self {
+        if nbuckets == 0 {
+            return RawTable { policy, cache_hashes, ..RawTable::new() };
+        }
+        let mut buckets = Vec::with_capacity(nbuckets);
+        buckets.resize_with(nbuckets, || None);
+        RawTable {
+            ctrl: vec![EMPTY; nbuckets + Group::WIDTH],
+            buckets,
+            items: 0,
+            growth_left: policy.capacity_for(nbuckets),
+            policy,
+            hashes: if cache_hashes { vec![0; nbuckets] } else { Vec::new() },
+            cache_hashes,
+        }
+    }
+
+    /// Returns an empty table of `nbuckets` buckets, with the same policy
+    /// and hash caching as this one.
+    pub(crate) fn empty_like(&self, nbuckets: usize) -> Self {
+        RawTable::with_buckets(nbuckets, self.policy, self.cache_hashes)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.items
+    }
+
+    #[cfg(test)]
+    pub(crate) fn nbuckets(&self) -> usize {
+        self.buckets.len()
+    }
+
+    /// Number of values the table holds before it has to resize.
+    pub(crate) fn capacity(&self) -> usize {
+        self.policy.capacity_for(self.buckets.len())
+    }
+
+    pub(crate) fn policy(&self) -> GrowthPolicy {
+        self.policy
+    }
+
+    pub(crate) fn caches_hashes(&self) -> bool {
+        self.cache_hashes
+    }
+
+    /// Chooses whether the table keeps the full hash of every value, so
+    /// that moving values around needs no `hasher`, and so that `find`
+    /// only calls `eq` on values whose hash matches.
+    pub(crate) fn set_cache_hashes<H>(&mut self, cache_hashes: bool, hasher: H)
+    where
+        H: Fn(&T) -> u64,
+    {
+        self.cache_hashes = cache_hashes;
+        self.hashes = if cache_hashes {
+            self.buckets.iter().map(|bucket| bucket.as_ref().map_or(0, &hasher)).collect()
+        } else {
+            Vec::new()
+        };
+    }
+
+    /// The cached hash of the value at `index`, if the table caches them.
+    fn cached_hash(&self, index: usize) -> Option<u64> {
+        self.hashes.get(index).copied()
+    }
+
+    /// Switches to `policy`, rebuilding the table for its load factor.
+    pub(crate) fn set_policy<H>(&mut self, policy: GrowthPolicy, hasher: H)
+    where
+        H: Fn(&T) -> u64,
+    {
+        self.policy = policy;
+        let nbuckets = match self.buckets.len() {
+            0 => 0,
+            n if policy.capacity_for(n) >= self.items => n.max(policy.buckets_for(1)),
+            _ => policy.buckets_for(self.items),
+        };
+        self.resize(nbuckets, hasher);
+    }
+
+    fn mask(&self) -> usize {
+        self.buckets.len() - 1
+    }
+
+    fn probe_seq(&self, hash: u64) -> ProbeSeq {
+        ProbeSeq {
+            pos: h1(hash, self.buckets.len()),
+            stride: 0,
+        }
+    }
+
+    /// Sets the control byte of the bucket at `index`, and its copy at the
+    /// end of `ctrl` if it has one.
+    fn set_ctrl(&mut self, index: usize, ctrl: u8) {
+        let mirror = (index.wrapping_sub(Group::WIDTH) & self.mask()) + Group::WIDTH;
+        self.ctrl[index] = ctrl;
+        self.ctrl[mirror] = ctrl;
+    }
+
+    /// Returns the index of the bucket holding the value with hash `hash`
+    /// for which `eq` returns `true`.
+    pub(crate) fn find<F>(&self, hash: u64, mut eq: F) -> Option<usize>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        if self.buckets.is_empty() {
+            return None;
+        }
+        let h2 = h2(hash);
+        let mut probe = self.probe_seq(hash);
+        loop {
+            let group = Group::load(&self.ctrl[probe.pos..]);
+            for bit in group.match_byte(h2) {
+                let index = (probe.pos + bit) & self.mask();
+                if let Some(ref value) = self.buckets[index] {
+                    if self.cached_hash(index).is_none_or(|cached| cached == hash) && eq(value) {
+                        return Some(index);
+                    }
+                }
+            }
+            if group.match_empty().any_bit_set() {
+                return None;
+            }
+            probe.move_next(self.mask());
+        }
+    }
+
+    /// Returns the index of the first `EMPTY` or `DELETED` bucket in the
+    /// probe sequence of `hash`.
+    fn find_insert_slot(&self, hash: u64) -> usize {
+        let mut probe = self.probe_seq(hash);
+        loop {
+            let group = Group::load(&self.ctrl[probe.pos..]);
+            if let Some(bit) = group.match_empty_or_deleted().lowest_set_bit() {
+                let index = (probe.pos + bit) & self.mask();
+                if is_full(self.ctrl[index]) {
+                    // In a table smaller than a group, the padding bytes
+                    // match but wrap onto the start of the table, which may
+                    // be full. The table's first group has a free bucket.
+                    let group = Group::load(&self.ctrl);
+                    return group.match_empty_or_deleted().lowest_set_bit().unwrap();
+                }
+                return index;
+            }
+            probe.move_next(self.mask());
+        }
+    }
+
+    pub(crate) fn get(&self, index: usize) -> &T {
+        self.buckets[index].as_ref().expect("empty bucket")
+    }
+
+    pub(crate) fn get_mut(&mut self, index: usize) -> &mut T {
+        self.buckets[index].as_mut().expect("empty bucket")
+    }
+
+    /// Whether `additional` more values can be inserted without a resize.
+    pub(crate) fn has_room(&self, additional: usize) -> bool {
+        additional <= self.growth_left
+    }
+
+    /// Number of buckets the table needs to make room for `additional`
+    /// more values. It is the current count when at least half of the
+    /// capacity is tombstones, so that reclaiming them is enough, and
+    /// otherwise grows by the policy's multiplier, or more if needed.
+    pub(crate) fn resize_target(&self, additional: usize) -> usize {
+        let items = self.items.checked_add(additional).expect("capacity overflow");
+        if items <= self.capacity() / 2 {
+            self.buckets.len()
+        } else {
+            let grown = self.policy.grown(self.buckets.len());
+            self.policy.buckets_for(items).max(grown)
+        }
+    }
+
+    /// Makes sure that `additional` more values can be inserted without
+    /// another resize, either by clearing out tombstones or by growing.
+    pub(crate) fn reserve<H>(&mut self, additional: usize, hasher: H)
+    where
+        H: Fn(&T) -> u64,
+    {
+        if self.has_room(additional) {
+            return;
+        }
+        match self.resize_target(additional) {
+            nbuckets if nbuckets == self.buckets.len() => self.rehash_in_place(hasher),
+            nbuckets => self.resize(nbuckets, hasher),
+        }
+    }
+
+    /// Shrinks the table as much as possible while it still holds
+    /// `min_capacity` values, or all of its current ones if that is more.
+    pub(crate) fn shrink_to<H>(&mut self, min_capacity: usize, hasher: H)
+    where
+        H: Fn(&T) -> u64,
+    {
+        let nbuckets = self.policy.buckets_for(self.items.max(min_capacity));
+        if nbuckets < self.buckets.len() {
+            self.resize(nbuckets, hasher);
+        }
+    }
+
+    /// Shrinks the table after a removal if the policy says it has become
+    /// too sparse, keeping room for the values to grow once.
+    pub(crate) fn shrink_if_sparse<H>(&mut self, hasher: H)
+    where
+        H: Fn(&T) -> u64,
+    {
+        if !self.policy.should_shrink(self.items, self.buckets.len()) {
+            return;
+        }
+        let room = (self.items as f64 * self.policy.growth_multiplier) as usize;
+        let nbuckets = self.policy.buckets_for(room.max(1));
+        if nbuckets < self.buckets.len() {
+            self.resize(nbuckets, hasher);
+        }
+    }
+
+    /// Moves every value into a fresh table of `nbuckets` buckets.
+    fn resize<H>(&mut self, nbuckets: usize, hasher: H)
+    where
+        H: Fn(&T) -> u64,
+    {
+        let new = self.empty_like(nbuckets);
+        let mut old = mem::replace(self, new);
+        for index in 0..old.buckets.len() {
+            if let Some(value) = old.buckets[index].take() {
+                let hash = old.cached_hash(index).unwrap_or_else(|| hasher(&value));
+                self.insert_no_grow(hash, value);
+            }
+        }
+    }
+
+    /// Clears every tombstone without allocating, by moving each value to
+    /// the bucket its probe sequence reaches first.
+    fn rehash_in_place<H>(&mut self, hasher: H)
+    where
+        H: Fn(&T) -> u64,
+    {
+        let nbuckets = self.buckets.len();
+        let mask = self.mask();
+
+        // From here on, `DELETED` marks a value which has not been placed
+        // yet, and `EMPTY` is free for the taking.
+        for ctrl in &mut self.ctrl[..nbuckets] {
+            *ctrl = if is_full(*ctrl) { DELETED } else { EMPTY };
+        }
+        if nbuckets < Group::WIDTH {
+            self.ctrl.copy_within(..nbuckets, Group::WIDTH);
+        } else {
+            self.ctrl.copy_within(..Group::WIDTH, nbuckets);
+        }
+
+        for index in 0..nbuckets {
+            if self.ctrl[index] != DELETED {
+                continue;
+            }
+            loop {
+                let hash = self.cached_hash(index).unwrap_or_else(|| hasher(self.get(index)));
+                let new_index = self.find_insert_slot(hash);
+
+                // Which group of its probe sequence a bucket falls in.
+                let start = h1(hash, nbuckets);
+                let probe_group = |pos: usize| (pos.wrapping_sub(start) & mask) / Group::WIDTH;
+
+                if probe_group(index) == probe_group(new_index) {
+                    // Already in the first group with room for it.
+                    self.set_ctrl(index, h2(hash));
+                    break;
+                }
+
+                let displaced = self.ctrl[new_index];
+                self.set_ctrl(new_index, h2(hash));
+                if displaced == EMPTY {
+                    self.set_ctrl(index, EMPTY);
+                    self.buckets[new_index] = self.buckets[index].take();
+                    if self.cache_hashes {
+                        self.hashes[new_index] = hash;
+                    }
+                    break;
+                }
+                // `new_index` held another value still to be placed: swap
+                // them, and place that one next.
+                self.buckets.swap(index, new_index);
+                if self.cache_hashes {
+                    self.hashes.swap(index, new_index);
+                }
+            }
+        }
+
+        self.growth_left = self.capacity() - self.items;
+    }
+
+    /// Inserts `value` and returns the index of its bucket. The caller has
+    /// reserved room for it and made sure it is not already in the table.
+    pub(crate) fn insert_no_grow(&mut self, hash: u64, value: T) -> usize {
+        let index = self.find_insert_slot(hash);
+        if self.ctrl[index] == EMPTY {
+            debug_assert!(self.growth_left > 0);
+            self.growth_left -= 1;
+        }
+        self.set_ctrl(index, h2(hash));
+        self.buckets[index] = Some(value);
+        if self.cache_hashes {
+            self.hashes[index] = hash;
+        }
+        self.items += 1;
+        index
+    }
+
+    /// Removes the value at `index`.
+    pub(crate) fn remove(&mut self, index: usize) -> T {
+        let value = self.buckets[index].take().expect("empty bucket");
+        self.items -= 1;
+
+        // If the bucket sits in a run of at least a group's worth of
+        // non-empty buckets, some probe may have seen a full group here and
+        // moved on, so it has to stay non-empty for lookups to go on past
+        // it.
+        let index_before = index.wrapping_sub(Group::WIDTH) & self.mask();
+        let empty_before = Group::load(&self.ctrl[index_before..]).match_empty();
+        let empty_after = Group::load(&self.ctrl[index..]).match_empty();
+        if empty_before.leading_zeros() + empty_after.trailing_zeros() >= Group::WIDTH {
+            self.set_ctrl(index, DELETED);
+        } else {
+            self.set_ctrl(index, EMPTY);
+            self.growth_left += 1;
+        }
+        value
+    }
+
+    /// Removes every value, keeping the buckets allocated.
+    pub(crate) fn clear(&mut self) {
+        for bucket in &mut self.buckets {
+            *bucket = None;
+        }
+        self.clear_ctrl();
+    }
+
+    fn clear_ctrl(&mut self) {
+        for ctrl in &mut self.ctrl {
+            *ctrl = EMPTY;
+        }
+        self.items = 0;
+        self.growth_left = self.capacity();
+    }
+
+    pub(crate) fn iter(&self) -> Iter<'_, T> {
+        Iter { buckets: self.buckets.iter() }
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> IterMut<'_, T> {
+        IterMut { buckets: self.buckets.iter_mut() }
+    }
+
+    /// Removes every value, returning them as an iterator. The table is
+    /// empty even if the iterator is dropped before it finishes, and still
+    /// holds the values not yielded yet if it is leaked instead.
+    pub(crate) fn drain(&mut self) -> Drain<'_, T> {
+        Drain { table: self, index: 0 }
+    }
+
+    /// Returns a cursor which visits each value once while values are being
+    /// removed from under it.
+    pub(crate) fn sweep(&mut self) -> Sweep<'_, T> {
+        Sweep {
+            table: self,
+            index: 0,
+        }
+    }
+}
+
+
+impl<T: Clone> Clone for RawTable<T> {
+    fn clone(&self) -> Self {
+        RawTable {
+            ctrl: self.ctrl.clone(),
+            buckets: self.buckets.clone(),
+            items: self.items,
+            growth_left: self.growth_left,
+            policy: self.policy,
+            hashes: self.hashes.clone(),
+            cache_hashes: self.cache_hashes,
+        }
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        self.ctrl.clone_from(&source.ctrl);
+        self.buckets.clone_from(&source.buckets);
+        self.items = source.items;
+        self.growth_left = source.growth_left;
+        self.policy = source.policy;
+        self.hashes.clone_from(&source.hashes);
+        self.cache_hashes = source.cache_hashes;
+    }
+}
+
+
+/// A table being emptied into another one a few buckets at a time, so
+/// that no single operation pays for moving every value.
+#[derive(Clone)]
+pub(crate) struct Migration<T> {
+    table: RawTable<T>,
+    /// Buckets before this one have been moved already.
+    next: usize,
+}
+
+
+impl<T> Migration<T> {
+    pub(crate) fn new(table: RawTable<T>) -> Self {
+        Migration { table, next: 0 }
+    }
+
+    /// The values which have not been moved yet.
+    pub(crate) fn table(&self) -> &RawTable<T> {
+        &self.table
+    }
+
+    pub(crate) fn table_mut(&mut self) -> &mut RawTable<T> {
+        &mut self.table
+    }
+
+    pub(crate) fn into_table(self) -> RawTable<T> {
+        self.table
+    }
+
+    /// Moves the values of the next `nbuckets` buckets into `into`, which
+    /// must have room for them, and returns whether the migration is done.
+    pub(crate) fn step<H>(&mut self, into: &mut RawTable<T>, nbuckets: usize, hasher: H) -> bool
+    where
+        H: Fn(&T) -> u64,
+    {
+        let end = self.table.buckets.len().min(self.next + nbuckets);
+        for index in self.next..end {
+            if self.table.buckets[index].is_some() {
+                let cached = self.table.cached_hash(index);
+                let value = self.table.remove(index);
+                into.insert_no_grow(cached.unwrap_or_else(|| hasher(&value)), value);
+            }
+        }
+        self.next = end;
+        self.table.len() == 0
+    }
+
+    /// Moves every remaining value into `into`, which must have room for
+    /// them.
+    pub(crate) fn finish<H>(mut self, into: &mut RawTable<T>, hasher: H)
+    where
+        H: Fn(&T) -> u64,
+    {
+        let nbuckets = self.table.buckets.len();
+        self.step(into, nbuckets, hasher);
+    }
+}
+
+
+pub(crate) struct Iter<'a, T: 'a> {
+    buckets: slice::Iter<'a, Option<T>>,
+}
+
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.buckets.by_ref().flatten().next()
+    }
+}
+
+
+pub(crate) struct IterMut<'a, T: 'a> {
+    buckets: slice::IterMut<'a, Option<T>>,
+}
+
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.buckets.by_ref().flatten().next()
+    }
+}
+
+
+pub(crate) struct IntoIter<T> {
+    buckets: vec::IntoIter<Option<T>>,
+}
+
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.buckets.by_ref().flatten().next()
+    }
+}
+
+
+impl<T> IntoIterator for RawTable<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter { buckets: self.buckets.into_iter() }
+    }
+}
+
+
+pub(crate) struct Drain<'a, T: 'a> {
+    table: &'a mut RawTable<T>,
+    /// Buckets before this one have been emptied already.
+    index: usize,
+}
+
+
+impl<'a, T> Iterator for Drain<'a, T> {
+    type Item = T;
+    fn next(&mut self) -> Option<Self::Item> {
+        // Each value is removed like any other, so that the table stays
+        // consistent if the iterator is leaked.
+        while self.index < self.table.buckets.len() {
+            let index = self.index;
+            self.index += 1;
+            if self.table.buckets[index].is_some() {
+                return Some(self.table.remove(index));
+            }
+        }
+        None
+    }
+}
+
+
+impl<'a, T> Drop for Drain<'a, T> {
+    fn drop(&mut self) {
+        self.table.clear();
+    }
+}
+
+
+pub(crate) struct Sweep<'a, T: 'a> {
+    table: &'a mut RawTable<T>,
+    index: usize,
+}
+
+
+impl<'a, T> Sweep<'a, T> {
+    /// Removes and returns the next value for which `pred` returns `true`.
+    pub(crate) fn next_if<F>(&mut self, mut pred: F) -> Option<T>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        // Removing never moves other values, so a plain scan works.
+        while self.index < self.table.buckets.len() {
+            let index = self.index;
+            self.index += 1;
+            if let Some(ref mut value) = self.table.buckets[index] {
+                if pred(value) {
+                    return Some(self.table.remove(index));
+                }
+            }
+        }
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that the control bytes agree with the buckets and with the
+    /// counters, and that every value can be found.
+    fn assert_consistent(table: &RawTable<u64>, hasher: impl Fn(&u64) -> u64) {
+        let nbuckets = table.buckets.len();
+        let mut tombstones = 0;
+        for index in 0..nbuckets {
+            let ctrl = table.ctrl[index];
+            match table.buckets[index] {
+                Some(value) => assert_eq!(ctrl, h2(hasher(&value))),
+                None => assert!(ctrl == EMPTY || ctrl == DELETED),
+            }
+            if ctrl == DELETED {
+                tombstones += 1;
+            }
+            if let (true, Some(value)) = (table.cache_hashes, table.buckets[index]) {
+                assert_eq!(table.hashes[index], hasher(&value));
+            }
+        }
+        if nbuckets < Group::WIDTH {
+            assert!(table.ctrl[nbuckets..Group::WIDTH].iter().all(|&ctrl| ctrl == EMPTY));
+            assert_eq!(table.ctrl[Group::WIDTH..], table.ctrl[..nbuckets]);
+        } else {
+            assert_eq!(table.ctrl[nbuckets..], table.ctrl[..Group::WIDTH]);
+        }
+        assert_eq!(table.iter().count(), table.items);
+        assert_eq!(table.growth_left, table.capacity() - table.items - tombstones);
+        for &value in table.iter() {
+            assert!(table.find(hasher(&value), |&v| v == value).is_some());
+        }
+    }
+
+    fn insert(table: &mut RawTable<u64>, hasher: impl Fn(&u64) -> u64 + Copy, value: u64) {
+        table.reserve(1, hasher);
+        table.insert_no_grow(hasher(&value), value);
+    }
+
+    fn remove(table: &mut RawTable<u64>, hasher: impl Fn(&u64) -> u64, value: u64) {
+        let index = table.find(hasher(&value), |&v| v == value).unwrap();
+        assert_eq!(table.remove(index), value);
+    }
+
+    /// The hash which `mix` turns into `mixed`.
+    fn unmix(mixed: u64) -> u64 {
+        // The inverse of `FIBONACCI` modulo 2^64, then of the fold, which
+        // leaves the high half as it is.
+        let folded = mixed.wrapping_mul(0xF1DE_83E1_9937_733D);
+        folded ^ folded >> 32
+    }
+
+    /// Spreads values over the table but keeps their 7-bit tags colliding.
+    fn spread(value: &u64) -> u64 {
+        unmix(value.wrapping_mul(FIBONACCI) >> 7)
+    }
+
+    /// Sends every value to the same home bucket, with distinct tags.
+    fn clump(value: &u64) -> u64 {
+        unmix(value << 57)
+    }
+
+    #[test]
+    fn mix() {
+        assert_eq!(super::mix(unmix(12345)), 12345);
+        assert_eq!(h2(unmix(0x7F << 57)), 0x7F);
+        assert_eq!(h1(unmix(0b101 << 54), 8), 0b101);
+        assert_eq!(h1(unmix(u64::MAX), 1), 0);
+        for bit in 0..64 {
+            assert_ne!(h1(1 << bit, 1 << 20), h1(0, 1 << 20), "bit {}", bit);
+        }
+    }
+
+    #[test]
+    fn poor_hashes_spread() {
+        // Hashes which only differ in their high bits, or in their low
+        // bits, still start probing all over the table.
+        for &hasher in &[(|&value| value << 40) as fn(&u64) -> u64, |&value| value] {
+            let mut table = RawTable::new();
+            for i in 0..1000 {
+                insert(&mut table, hasher, i);
+            }
+            let nbuckets = table.buckets.len();
+            let mut homes: Vec<_> = (0..1000).map(|i| h1(hasher(&i), nbuckets)).collect();
+            homes.sort();
+            homes.dedup();
+            assert!(homes.len() > 500, "{} home buckets", homes.len());
+            assert_consistent(&table, hasher);
+        }
+    }
+
+    #[test]
+    fn insert_and_remove() {
+        for &(hasher, cache_hashes) in &[
+            (spread as fn(&u64) -> u64, false),
+            (clump, false),
+            (spread, true),
+            (clump, true),
+        ] {
+            let mut table = RawTable::new();
+            table.set_cache_hashes(cache_hashes, hasher);
+            for i in 0..200 {
+                insert(&mut table, hasher, i);
+                assert_consistent(&table, hasher);
+            }
+            for i in (0..200).step_by(3) {
+                remove(&mut table, hasher, i);
+                assert_consistent(&table, hasher);
+            }
+            for i in 0..200 {
+                assert_eq!(table.find(hasher(&i), |&v| v == i).is_some(), i % 3 != 0);
+            }
+        }
+    }
+
+    #[test]
+    fn small_table() {
+        let mut table = RawTable::new();
+        for i in 0..3 {
+            insert(&mut table, clump, i);
+        }
+        assert_eq!(table.nbuckets(), 4);
+        assert_consistent(&table, clump);
+        remove(&mut table, clump, 1);
+        insert(&mut table, clump, 3);
+        assert_consistent(&table, clump);
+    }
+
+    #[test]
+    fn tombstones_are_rehashed_in_place() {
+        let mut table = RawTable::with_capacity(768);
+        let nbuckets = table.nbuckets();
+        for i in 0..768 {
+            insert(&mut table, clump, i);
+        }
+        // The clump fills whole groups, so removing from it leaves
+        // tombstones which keep eating into the room for inserts.
+        for i in 0..500 {
+            remove(&mut table, clump, i);
+        }
+        assert!(table.growth_left < 232);
+        for i in 768..1000 {
+            insert(&mut table, clump, i);
+        }
+        assert_consistent(&table, clump);
+        assert_eq!(table.nbuckets(), nbuckets);
+        assert_eq!(table.len(), 500);
+    }
+
+    #[test]
+    fn cached_hashes_are_used() {
+        let mut table = RawTable::new();
+        table.set_cache_hashes(true, spread);
+        for i in 0..100 {
+            insert(&mut table, spread, i);
+        }
+        // Moving values around reads their cached hashes, so it never
+        // calls the hasher.
+        table.resize(1024, |_: &u64| -> u64 { panic!("rehashed") });
+        assert_consistent(&table, spread);
+
+        table.set_cache_hashes(false, spread);
+        assert!(table.hashes.is_empty());
+        assert_consistent(&table, spread);
+    }
+
+    #[test]
+    fn sweep_visits_each_value_once() {
+        let mut table = RawTable::new();
+        for i in 0..100 {
+            insert(&mut table, spread, i);
+        }
+        let mut seen = Vec::new();
+        let mut sweep = table.sweep();
+        while let Some(v) = sweep.next_if(|&mut v| {
+            seen.push(v);
+            v % 2 == 0
+        }) {
+            assert_eq!(v % 2, 0);
+        }
+        seen.sort();
+        assert_eq!(seen, (0..100).collect::<Vec<_>>());
+        assert_eq!(table.len(), 50);
+        assert_consistent(&table, spread);
+    }
+
+    #[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+    #[test]
+    fn groups_agree() {
+        let bytes = [0x01, 0x00, EMPTY, 0x01, DELETED, 0x7f, 0x01, 0x02, EMPTY, EMPTY, 0x01, DELETED, 0x42, 0x00, 0x7f, 0x03];
+        let sse2 = sse2::Group::load(&bytes);
+        let low = generic::Group::load(&bytes[..8]);
+        let high = generic::Group::load(&bytes[8..]);
+        // Glues the generic matches of both halves into one 16-bit mask.
+        let both = |f: &dyn Fn(&generic::Group) -> BitMask| f(&low).0 | f(&high).0 << 8;
+        for &byte in &[0x00, 0x01, 0x7f, 0x42] {
+            assert_eq!(sse2.match_byte(byte).0, both(&|group| group.match_byte(byte)));
+        }
+        assert_eq!(sse2.match_empty().0, both(&generic::Group::match_empty));
+        assert_eq!(sse2.match_empty_or_deleted().0, both(&generic::Group::match_empty_or_deleted));
+        assert_eq!(sse2.match_full().0, both(&generic::Group::match_full));
+    }
+}
@@ -0,0 +1,78 @@
+//! Control-byte groups probed 8 at a time in a `u64`, for targets without
+//! SSE2.
+
+use std::convert::TryInto;
+
+use super::BitMask;
+
+
+/// Every byte set to `byte`.
+const fn repeat(byte: u8) -> u64 {
+    u64::from_ne_bytes([byte; 8])
+}
+
+
+/// The low seven bits of every byte.
+const LOW: u64 = repeat(0x7f);
+/// The high bit of every byte.
+const HIGH: u64 = repeat(0x80);
+
+
+pub(crate) struct Group(u64);
+
+
+impl Group {
+    pub(crate) const WIDTH: usize = 8;
+
+    /// Loads the group starting at the first byte of `ctrl`.
+    pub(crate) fn load(ctrl: &[u8]) -> Group {
+        Group(u64::from_le_bytes(ctrl[..Group::WIDTH].try_into().unwrap()))
+    }
+
+    /// Packs a word with only high bits set into one bit per byte.
+    fn bitmask(high_bits: u64) -> BitMask {
+        // Multiplying moves the high bit of byte `i` to bit `56 + i`, and
+        // none of the other partial products overlap.
+        BitMask(((high_bits >> 7).wrapping_mul(0x0102_0408_1020_4080) >> 56) as u16)
+    }
+
+    /// Bytes equal to `byte`.
+    pub(crate) fn match_byte(&self, byte: u8) -> BitMask {
+        let cmp = self.0 ^ repeat(byte);
+        // A byte's high bit ends up set iff the byte was zero. Adding to the
+        // low seven bits alone never carries into the next byte.
+        Group::bitmask(!(((cmp & LOW).wrapping_add(LOW)) | cmp) & HIGH)
+    }
+
+    /// `EMPTY` is the only control byte with both of its top bits set.
+    pub(crate) fn match_empty(&self) -> BitMask {
+        Group::bitmask(self.0 & (self.0 << 1) & HIGH)
+    }
+
+    pub(crate) fn match_empty_or_deleted(&self) -> BitMask {
+        Group::bitmask(self.0 & HIGH)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn match_full(&self) -> BitMask {
+        Group::bitmask(!self.0 & HIGH)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::EMPTY;
+
+    #[test]
+    fn match_byte() {
+        let ctrl = [0x01, 0x00, EMPTY, 0x01, 0x80, 0x7f, 0x01, 0x02];
+        let group = Group::load(&ctrl);
+        assert_eq!(group.match_byte(0x01).0, 0b0100_1001);
+        assert_eq!(group.match_byte(0x00).0, 0b0000_0010);
+        assert_eq!(group.match_empty().0, 0b0000_0100);
+        assert_eq!(group.match_empty_or_deleted().0, 0b0001_0100);
+        assert_eq!(group.match_full().0, 0b1110_1011);
+    }
+}
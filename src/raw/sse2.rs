@@ -0,0 +1,47 @@
+//! Control-byte groups probed 16 at a time with SSE2.
+//!
+//! This module is only compiled when SSE2 is enabled for the target, which
+//! is what makes calling its intrinsics sound.
+
+use std::arch::x86_64::{__m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8, _mm_set1_epi8};
+
+use super::{BitMask, EMPTY};
+
+
+pub(crate) struct Group(__m128i);
+
+
+impl Group {
+    pub(crate) const WIDTH: usize = 16;
+
+    /// Loads the group starting at the first byte of `ctrl`.
+    pub(crate) fn load(ctrl: &[u8]) -> Group {
+        let bytes = &ctrl[..Group::WIDTH];
+        // SAFETY: `bytes` is 16 readable bytes, and the load is unaligned.
+        Group(unsafe { _mm_loadu_si128(bytes.as_ptr() as *const __m128i) })
+    }
+
+    /// Bytes equal to `byte`.
+    pub(crate) fn match_byte(&self, byte: u8) -> BitMask {
+        // SAFETY: SSE2 is available, see the module docs.
+        unsafe {
+            let cmp = _mm_cmpeq_epi8(self.0, _mm_set1_epi8(byte as i8));
+            BitMask(_mm_movemask_epi8(cmp) as u16)
+        }
+    }
+
+    pub(crate) fn match_empty(&self) -> BitMask {
+        self.match_byte(EMPTY)
+    }
+
+    /// Bytes with their high bit set, which are exactly the special ones.
+    pub(crate) fn match_empty_or_deleted(&self) -> BitMask {
+        // SAFETY: SSE2 is available, see the module docs.
+        BitMask(unsafe { _mm_movemask_epi8(self.0) } as u16)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn match_full(&self) -> BitMask {
+        BitMask(!self.match_empty_or_deleted().0)
+    }
+}
@@ -0,0 +1,370 @@
+//! `Serialize` and `Deserialize` for the maps and sets of this crate, with
+//! the `serde` feature.
+//!
+//! Maps serialize as maps, sets as sequences, and a `HashMultiMap` as a map
+//! from each key to the sequence of its values. Deserializing sizes the
+//! table once, from the length the format announces, and lets later
+//! duplicates of a key win, like `std` does. Wrap the target in `Strict`,
+//! or use `strict` as a `deserialize_with` function, to reject them
+//! instead.
+
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::mem;
+
+use ::serde::de::{Deserialize, Deserializer, Error, MapAccess, SeqAccess, Visitor};
+use ::serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+
+use crate::{Entry, HashMap, HashMultiMap, HashSet};
+
+
+/// A map or set which fails to deserialize if a key shows up twice.
+///
+/// ```
+/// # use hashmap::{serde::Strict, HashMap};
+/// # use serde::de::{value::MapDeserializer, Deserialize, value::Error};
+/// let input = vec![("a", 1), ("a", 2)];
+/// let map = Strict::<HashMap<&str, i32>>::deserialize(MapDeserializer::<_, Error>::new(input.into_iter()));
+/// assert!(map.is_err());
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Strict<T>(pub T);
+
+
+/// Deserializes a map or set like `Strict` does, for use in
+/// `#[serde(deserialize_with = "hashmap::serde::strict")]`.
+pub fn strict<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    Strict<T>: Deserialize<'de>,
+{
+    Strict::deserialize(deserializer).map(|strict| strict.0)
+}
+
+
+/// Caps a size hint from the input, which may lie, to a megabyte worth of
+/// `T`s.
+fn cautious<T>(hint: Option<usize>) -> usize {
+    const MAX_BYTES: usize = 1024 * 1024;
+    hint.unwrap_or(0).min(MAX_BYTES / mem::size_of::<T>().max(1))
+}
+
+
+impl<K, V, S> Serialize for HashMap<K, V, S>
+where
+    K: Serialize + Hash + Eq,
+    V: Serialize,
+    S: BuildHasher,
+{
+    fn serialize<T: Serializer>(&self, serializer: T) -> Result<T::Ok, T::Error> {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for (key, value) in self {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+
+impl<T, S> Serialize for HashSet<T, S>
+where
+    T: Serialize + Hash + Eq,
+    S: BuildHasher,
+{
+    fn serialize<U: Serializer>(&self, serializer: U) -> Result<U::Ok, U::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.len()))?;
+        for value in self {
+            seq.serialize_element(value)?;
+        }
+        seq.end()
+    }
+}
+
+
+impl<K, V, S> Serialize for HashMultiMap<K, V, S>
+where
+    K: Serialize + Hash + Eq,
+    V: Serialize,
+    S: BuildHasher,
+{
+    fn serialize<T: Serializer>(&self, serializer: T) -> Result<T::Ok, T::Error> {
+        let mut map = serializer.serialize_map(Some(self.len_keys()))?;
+        for (key, values) in self {
+            map.serialize_entry(key, values)?;
+        }
+        map.end()
+    }
+}
+
+
+struct TableVisitor<T> {
+    strict: bool,
+    marker: PhantomData<T>,
+}
+
+
+impl<T> TableVisitor<T> {
+    fn new(strict: bool) -> Self {
+        TableVisitor { strict, marker: PhantomData }
+    }
+}
+
+
+impl<'de, K, V, S> Visitor<'de> for TableVisitor<HashMap<K, V, S>>
+where
+    K: Deserialize<'de> + Hash + Eq,
+    V: Deserialize<'de>,
+    S: BuildHasher + Default,
+{
+    type Value = HashMap<K, V, S>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a map")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+        let capacity = cautious::<(K, V)>(access.size_hint());
+        let mut map = HashMap::with_capacity_and_hasher(capacity, S::default());
+        while let Some((key, value)) = access.next_entry()? {
+            match map.entry(key) {
+                Entry::Occupied(_) if self.strict => return Err(A::Error::custom("duplicate key in map")),
+                Entry::Occupied(mut entry) => {
+                    entry.insert(value);
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(value);
+                }
+            }
+        }
+        Ok(map)
+    }
+}
+
+
+impl<'de, T, S> Visitor<'de> for TableVisitor<HashSet<T, S>>
+where
+    T: Deserialize<'de> + Hash + Eq,
+    S: BuildHasher + Default,
+{
+    type Value = HashSet<T, S>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a sequence")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+        let capacity = cautious::<T>(access.size_hint());
+        let mut set = HashSet::with_capacity_and_hasher(capacity, S::default());
+        while let Some(value) = access.next_element()? {
+            if !set.insert(value) && self.strict {
+                return Err(A::Error::custom("duplicate value in set"));
+            }
+        }
+        Ok(set)
+    }
+}
+
+
+impl<'de, K, V, S> Visitor<'de> for TableVisitor<HashMultiMap<K, V, S>>
+where
+    K: Deserialize<'de> + Hash + Eq,
+    V: Deserialize<'de>,
+    S: BuildHasher + Default,
+{
+    type Value = HashMultiMap<K, V, S>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a map of sequences")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+        let capacity = cautious::<(K, V)>(access.size_hint());
+        let mut map = HashMultiMap::with_capacity_and_hasher(capacity, S::default());
+        while let Some((key, values)) = access.next_entry::<K, Vec<V>>()? {
+            if self.strict && map.contains_key(&key) {
+                return Err(A::Error::custom("duplicate key in map"));
+            }
+            map.insert_all(key, values);
+        }
+        Ok(map)
+    }
+}
+
+
+impl<'de, K, V, S> Deserialize<'de> for HashMap<K, V, S>
+where
+    K: Deserialize<'de> + Hash + Eq,
+    V: Deserialize<'de>,
+    S: BuildHasher + Default,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(TableVisitor::<Self>::new(false))
+    }
+}
+
+
+impl<'de, K, V, S> Deserialize<'de> for Strict<HashMap<K, V, S>>
+where
+    K: Deserialize<'de> + Hash + Eq,
+    V: Deserialize<'de>,
+    S: BuildHasher + Default,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(TableVisitor::<HashMap<K, V, S>>::new(true)).map(Strict)
+    }
+}
+
+
+impl<'de, T, S> Deserialize<'de> for HashSet<T, S>
+where
+    T: Deserialize<'de> + Hash + Eq,
+    S: BuildHasher + Default,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(TableVisitor::<Self>::new(false))
+    }
+}
+
+
+impl<'de, T, S> Deserialize<'de> for Strict<HashSet<T, S>>
+where
+    T: Deserialize<'de> + Hash + Eq,
+    S: BuildHasher + Default,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(TableVisitor::<HashSet<T, S>>::new(true)).map(Strict)
+    }
+}
+
+
+impl<'de, K, V, S> Deserialize<'de> for HashMultiMap<K, V, S>
+where
+    K: Deserialize<'de> + Hash + Eq,
+    V: Deserialize<'de>,
+    S: BuildHasher + Default,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(TableVisitor::<Self>::new(false))
+    }
+}
+
+
+impl<'de, K, V, S> Deserialize<'de> for Strict<HashMultiMap<K, V, S>>
+where
+    K: Deserialize<'de> + Hash + Eq,
+    V: Deserialize<'de>,
+    S: BuildHasher + Default,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(TableVisitor::<HashMultiMap<K, V, S>>::new(true)).map(Strict)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::serde::de::value::{self, MapDeserializer};
+    use serde_test::{assert_de_tokens, assert_de_tokens_error, assert_ser_tokens, assert_tokens, Token};
+
+    #[test]
+    fn map() {
+        let map: HashMap<_, _> = vec![("a", 1)].into_iter().collect();
+        assert_tokens(&map, &[
+            Token::Map { len: Some(1) },
+            Token::BorrowedStr("a"),
+            Token::I32(1),
+            Token::MapEnd,
+        ]);
+    }
+
+    #[test]
+    fn map_duplicates() {
+        let map: HashMap<_, _> = vec![("a", 2)].into_iter().collect();
+        let tokens = [
+            Token::Map { len: Some(2) },
+            Token::BorrowedStr("a"),
+            Token::I32(1),
+            Token::BorrowedStr("a"),
+            Token::I32(2),
+            Token::MapEnd,
+        ];
+        assert_de_tokens(&map, &tokens);
+        assert_de_tokens_error::<Strict<HashMap<&str, i32>>>(&tokens, "duplicate key in map");
+    }
+
+    #[test]
+    fn presizes() {
+        let entries = (0..100).map(|i| (i, i));
+        let map = HashMap::<u32, u32>::deserialize(MapDeserializer::<_, value::Error>::new(entries)).unwrap();
+        assert_eq!(map.len(), 100);
+        assert_eq!(map.capacity(), HashMap::<u32, u32>::with_capacity(100).capacity());
+
+        // A length too large to trust doesn't allocate all of it.
+        struct Lying;
+        impl Iterator for Lying {
+            type Item = (u32, u32);
+            fn next(&mut self) -> Option<(u32, u32)> { None }
+            fn size_hint(&self) -> (usize, Option<usize>) { (usize::MAX, Some(usize::MAX)) }
+        }
+        let map = HashMap::<u32, u32>::deserialize(MapDeserializer::<_, value::Error>::new(Lying)).unwrap();
+        assert!(map.capacity() < 1 << 20);
+    }
+
+    #[test]
+    fn set() {
+        let set: HashSet<_> = vec![1].into_iter().collect();
+        assert_tokens(&set, &[Token::Seq { len: Some(1) }, Token::I32(1), Token::SeqEnd]);
+
+        let tokens = [Token::Seq { len: Some(2) }, Token::I32(1), Token::I32(1), Token::SeqEnd];
+        assert_de_tokens(&set, &tokens);
+        assert_de_tokens_error::<Strict<HashSet<i32>>>(&tokens, "duplicate value in set");
+    }
+
+    #[test]
+    fn multimap() {
+        let map: HashMultiMap<_, _> = vec![("a", 1), ("a", 2)].into_iter().collect();
+        assert_ser_tokens(&map, &[
+            Token::Map { len: Some(1) },
+            Token::Str("a"),
+            Token::Seq { len: Some(2) },
+            Token::I32(1),
+            Token::I32(2),
+            Token::SeqEnd,
+            Token::MapEnd,
+        ]);
+
+        let entries = || {
+            let entries = vec![("a", vec![1]), ("b", vec![]), ("a", vec![2])];
+            MapDeserializer::<_, value::Error>::new(entries.into_iter().map(|(k, v)| (String::from(k), v)))
+        };
+        let map = HashMultiMap::<String, i32>::deserialize(entries()).unwrap();
+        assert_eq!(map.get_slice("a"), [1, 2]);
+        assert!(!map.contains_key("b"));
+        assert_eq!(map.len_values(), 2);
+        assert!(Strict::<HashMultiMap<String, i32>>::deserialize(entries()).is_err());
+    }
+
+    #[test]
+    fn strict() {
+        #[derive(Debug, PartialEq)]
+        struct Config(HashMap<String, i32>);
+        impl<'de> Deserialize<'de> for Config {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                super::strict(deserializer).map(Config)
+            }
+        }
+
+        let config = Config(vec![(String::from("a"), 1)].into_iter().collect());
+        assert_de_tokens(&config, &[Token::Map { len: None }, Token::Str("a"), Token::I32(1), Token::MapEnd]);
+        assert_de_tokens_error::<Config>(&[
+            Token::Map { len: None },
+            Token::Str("a"),
+            Token::I32(1),
+            Token::Str("a"),
+            Token::I32(1),
+            Token::MapEnd,
+        ], "duplicate key in map");
+    }
+}
@@ -0,0 +1,613 @@
+//! A hash set, as a `HashMap` whose values are `()`.
+
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::iter::{Chain, FromIterator};
+use std::ops::{BitAnd, BitOr, BitXor, Sub};
+
+use crate::{Entry, HashMap};
+
+
+/// A hash set, storing each value as the key of a `HashMap<T, ()>`.
+///
+/// Each bucket is an `Option<(T, ())>`, as big as an `Option<T>`: the `()`
+/// values take no storage, and all that remains besides `T` is the
+/// bucket's `Option` discriminant, if `T` has no niche to hide it in, and
+/// its control byte. The set shares the map's table, hashing and tuning
+/// knobs.
+pub struct HashSet<T, S = RandomState> {
+    map: HashMap<T, (), S>,
+}
+
+
+impl<T> HashSet<T, RandomState> {
+    pub fn new() -> Self {
+        HashSet { map: HashMap::new() }
+    }
+
+    /// Creates an empty set able to hold `capacity` values without
+    /// resizing.
+    pub fn with_capacity(capacity: usize) -> Self {
+        HashSet { map: HashMap::with_capacity(capacity) }
+    }
+}
+
+
+impl<T, S> HashSet<T, S> {
+    /// Creates an empty set which will use `hash_builder` to hash values.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        HashSet { map: HashMap::with_hasher(hash_builder) }
+    }
+
+    /// Creates an empty set able to hold `capacity` values without
+    /// resizing, which will use `hash_builder` to hash values.
+    pub fn with_capacity_and_hasher(capacity: usize, hash_builder: S) -> Self {
+        HashSet { map: HashMap::with_capacity_and_hasher(capacity, hash_builder) }
+    }
+
+    /// Returns a reference to the set's `BuildHasher`.
+    pub fn hasher(&self) -> &S {
+        self.map.hasher()
+    }
+
+    /// Number of values the set can hold without resizing.
+    pub fn capacity(&self) -> usize {
+        self.map.capacity()
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { inner: self.map.iter() }
+    }
+
+    /// Keeps only the values for which `f` returns `true`.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.map.retain(|value, _| f(value));
+    }
+
+    /// Removes every value, keeping the allocated memory.
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+}
+
+
+impl<T, S> HashSet<T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+    /// Makes room for `additional` more values; see `HashMap::reserve`.
+    pub fn reserve(&mut self, additional: usize) {
+        self.map.reserve(additional);
+    }
+
+    /// Shrinks the table as much as possible while it still holds the
+    /// current values.
+    pub fn shrink_to_fit(&mut self) {
+        self.map.shrink_to_fit();
+    }
+
+    /// Adds `value` to the set, and returns whether it was not there yet.
+    /// An equal value already in the set is kept.
+    pub fn insert(&mut self, value: T) -> bool {
+        self.map.insert(value, ()).is_none()
+    }
+
+    /// Adds `value` to the set, returning the equal value it replaces, if
+    /// any.
+    pub fn replace(&mut self, value: T) -> Option<T> {
+        match self.map.entry(value) {
+            Entry::Occupied(entry) => Some(entry.replace_key()),
+            Entry::Vacant(entry) => {
+                entry.insert(());
+                None
+            }
+        }
+    }
+
+    pub fn contains<Q>(&self, value: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.contains_key(value)
+    }
+
+    /// Returns the value in the set equal to `value`.
+    pub fn get<Q>(&self, value: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get_key_value(value).map(|(value, _)| value)
+    }
+
+    /// Removes `value` from the set, and returns whether it was there.
+    pub fn remove<Q>(&mut self, value: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.remove(value).is_some()
+    }
+
+    /// Removes the value equal to `value` from the set and returns it.
+    pub fn take<Q>(&mut self, value: &Q) -> Option<T>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.remove_entry(value).map(|(value, _)| value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Visits the values in `self` or `other`, without duplicates.
+    pub fn union<'a>(&'a self, other: &'a HashSet<T, S>) -> Union<'a, T, S> {
+        Union { inner: self.iter().chain(other.difference(self)) }
+    }
+
+    /// Visits the values in both `self` and `other`.
+    pub fn intersection<'a>(&'a self, other: &'a HashSet<T, S>) -> Intersection<'a, T, S> {
+        // Walk the smaller set, and look its values up in the bigger one.
+        let (small, big) = if self.len() <= other.len() { (self, other) } else { (other, self) };
+        Intersection { iter: small.iter(), other: big }
+    }
+
+    /// Visits the values in `self` but not in `other`.
+    pub fn difference<'a>(&'a self, other: &'a HashSet<T, S>) -> Difference<'a, T, S> {
+        Difference { iter: self.iter(), other }
+    }
+
+    /// Visits the values in exactly one of `self` and `other`.
+    pub fn symmetric_difference<'a>(
+        &'a self,
+        other: &'a HashSet<T, S>,
+    ) -> SymmetricDifference<'a, T, S> {
+        SymmetricDifference { inner: self.difference(other).chain(other.difference(self)) }
+    }
+
+    /// Whether every value in `self` is in `other`.
+    pub fn is_subset(&self, other: &HashSet<T, S>) -> bool {
+        self.len() <= other.len() && self.iter().all(|value| other.contains(value))
+    }
+
+    /// Whether every value in `other` is in `self`.
+    pub fn is_superset(&self, other: &HashSet<T, S>) -> bool {
+        other.is_subset(self)
+    }
+
+    /// Whether `self` and `other` have no value in common.
+    pub fn is_disjoint(&self, other: &HashSet<T, S>) -> bool {
+        self.intersection(other).next().is_none()
+    }
+}
+
+
+impl<T, S: Default> Default for HashSet<T, S> {
+    fn default() -> Self {
+        HashSet::with_hasher(S::default())
+    }
+}
+
+
+impl<T, S> Clone for HashSet<T, S>
+where
+    T: Clone,
+    S: Clone,
+{
+    fn clone(&self) -> Self {
+        HashSet { map: self.map.clone() }
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        self.map.clone_from(&source.map);
+    }
+}
+
+
+impl<T, S> fmt::Debug for HashSet<T, S>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+
+impl<T, S> PartialEq for HashSet<T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.is_subset(other)
+    }
+}
+
+
+impl<T, S> Eq for HashSet<T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+}
+
+
+pub struct Iter<'a, T: 'a> {
+    inner: crate::Iter<'a, T, ()>,
+}
+
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(value, _)| value)
+    }
+}
+
+
+impl<'a, T, S> IntoIterator for &'a HashSet<T, S> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+
+pub struct IntoIter<T> {
+    inner: crate::IntoIter<T, ()>,
+}
+
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(value, _)| value)
+    }
+}
+
+
+impl<T, S> IntoIterator for HashSet<T, S> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter { inner: self.map.into_iter() }
+    }
+}
+
+
+impl<T, S> FromIterator<T> for HashSet<T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher + Default,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut set = HashSet::with_hasher(S::default());
+        set.extend(iter);
+        set
+    }
+}
+
+
+impl<T, S> Extend<T> for HashSet<T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.map.extend(iter.into_iter().map(|value| (value, ())));
+    }
+}
+
+
+impl<'a, T, S> Extend<&'a T> for HashSet<T, S>
+where
+    T: Hash + Eq + Copy,
+    S: BuildHasher,
+{
+    fn extend<I: IntoIterator<Item = &'a T>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().copied());
+    }
+}
+
+
+impl<T, const N: usize> From<[T; N]> for HashSet<T, RandomState>
+where
+    T: Hash + Eq,
+{
+    fn from(values: [T; N]) -> Self {
+        IntoIterator::into_iter(values).collect()
+    }
+}
+
+
+pub struct Union<'a, T: 'a, S: 'a> {
+    inner: Chain<Iter<'a, T>, Difference<'a, T, S>>,
+}
+
+
+impl<'a, T, S> Iterator for Union<'a, T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+    type Item = &'a T;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+
+pub struct Intersection<'a, T: 'a, S: 'a> {
+    iter: Iter<'a, T>,
+    other: &'a HashSet<T, S>,
+}
+
+
+impl<'a, T, S> Iterator for Intersection<'a, T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+    type Item = &'a T;
+    fn next(&mut self) -> Option<Self::Item> {
+        let other = self.other;
+        self.iter.find(|value| other.contains(value))
+    }
+}
+
+
+pub struct Difference<'a, T: 'a, S: 'a> {
+    iter: Iter<'a, T>,
+    other: &'a HashSet<T, S>,
+}
+
+
+impl<'a, T, S> Iterator for Difference<'a, T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+    type Item = &'a T;
+    fn next(&mut self) -> Option<Self::Item> {
+        let other = self.other;
+        self.iter.find(|value| !other.contains(value))
+    }
+}
+
+
+pub struct SymmetricDifference<'a, T: 'a, S: 'a> {
+    inner: Chain<Difference<'a, T, S>, Difference<'a, T, S>>,
+}
+
+
+impl<'a, T, S> Iterator for SymmetricDifference<'a, T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+    type Item = &'a T;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+
+impl<T, S> BitOr<&HashSet<T, S>> for &HashSet<T, S>
+where
+    T: Hash + Eq + Clone,
+    S: BuildHasher + Default,
+{
+    type Output = HashSet<T, S>;
+    fn bitor(self, other: &HashSet<T, S>) -> HashSet<T, S> {
+        self.union(other).cloned().collect()
+    }
+}
+
+
+impl<T, S> BitAnd<&HashSet<T, S>> for &HashSet<T, S>
+where
+    T: Hash + Eq + Clone,
+    S: BuildHasher + Default,
+{
+    type Output = HashSet<T, S>;
+    fn bitand(self, other: &HashSet<T, S>) -> HashSet<T, S> {
+        self.intersection(other).cloned().collect()
+    }
+}
+
+
+impl<T, S> Sub<&HashSet<T, S>> for &HashSet<T, S>
+where
+    T: Hash + Eq + Clone,
+    S: BuildHasher + Default,
+{
+    type Output = HashSet<T, S>;
+    fn sub(self, other: &HashSet<T, S>) -> HashSet<T, S> {
+        self.difference(other).cloned().collect()
+    }
+}
+
+
+impl<T, S> BitXor<&HashSet<T, S>> for &HashSet<T, S>
+where
+    T: Hash + Eq + Clone,
+    S: BuildHasher + Default,
+{
+    type Output = HashSet<T, S>;
+    fn bitxor(self, other: &HashSet<T, S>) -> HashSet<T, S> {
+        self.symmetric_difference(other).cloned().collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted<'a, I: Iterator<Item = &'a i32>>(iter: I) -> Vec<i32> {
+        let mut values: Vec<_> = iter.copied().collect();
+        values.sort();
+        values
+    }
+
+    #[test]
+    fn insert() {
+        let mut set = HashSet::new();
+        assert!(set.insert(1));
+        assert!(!set.insert(1));
+        assert!(set.insert(2));
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn contains() {
+        let set = HashSet::from([1, 2]);
+        assert!(set.contains(&1));
+        assert!(!set.contains(&3));
+    }
+
+    #[test]
+    fn remove() {
+        let mut set = HashSet::from([1, 2]);
+        assert!(set.remove(&1));
+        assert!(!set.remove(&1));
+        assert_eq!(set.len(), 1);
+    }
+
+    /// Compares equal when the ids do, but remembers which instance it is.
+    #[derive(Debug)]
+    struct Tagged(i32, &'static str);
+
+    impl PartialEq for Tagged {
+        fn eq(&self, other: &Self) -> bool {
+            self.0 == other.0
+        }
+    }
+
+    impl Eq for Tagged {}
+
+    impl Hash for Tagged {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            self.0.hash(state);
+        }
+    }
+
+    #[test]
+    fn get_take_replace() {
+        let mut set = HashSet::new();
+        set.insert(Tagged(1, "first"));
+        assert!(!set.insert(Tagged(1, "second")));
+        assert_eq!(set.get(&Tagged(1, "")).unwrap().1, "first");
+
+        assert_eq!(set.replace(Tagged(1, "third")).unwrap().1, "first");
+        assert_eq!(set.get(&Tagged(1, "")).unwrap().1, "third");
+        assert!(set.replace(Tagged(2, "new")).is_none());
+
+        assert_eq!(set.take(&Tagged(1, "")).unwrap().1, "third");
+        assert!(set.take(&Tagged(1, "")).is_none());
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    fn zero_sized_values() {
+        use std::mem::size_of;
+        fn same_size<T>() -> bool {
+            size_of::<Option<(T, ())>>() == size_of::<Option<T>>()
+        }
+        assert!(same_size::<u8>());
+        assert!(same_size::<u64>());
+        assert!(same_size::<String>());
+        assert!(same_size::<Box<u32>>());
+        assert!(same_size::<(u32, u16)>());
+    }
+
+    #[test]
+    fn iter() {
+        let set = HashSet::from([3, 1, 2]);
+        assert_eq!(sorted(set.iter()), [1, 2, 3]);
+        let mut values: Vec<_> = set.into_iter().collect();
+        values.sort();
+        assert_eq!(values, [1, 2, 3]);
+    }
+
+    #[test]
+    fn union() {
+        let a = HashSet::from([1, 2, 3]);
+        let b = HashSet::from([3, 4]);
+        assert_eq!(sorted(a.union(&b)), [1, 2, 3, 4]);
+        assert_eq!(a.union(&b).count(), 4);
+    }
+
+    #[test]
+    fn intersection() {
+        let a = HashSet::from([1, 2, 3]);
+        let b = HashSet::from([2, 3, 4, 5]);
+        assert_eq!(sorted(a.intersection(&b)), [2, 3]);
+        assert_eq!(sorted(b.intersection(&a)), [2, 3]);
+    }
+
+    #[test]
+    fn difference() {
+        let a = HashSet::from([1, 2, 3]);
+        let b = HashSet::from([2, 3, 4]);
+        assert_eq!(sorted(a.difference(&b)), [1]);
+        assert_eq!(sorted(b.difference(&a)), [4]);
+    }
+
+    #[test]
+    fn symmetric_difference() {
+        let a = HashSet::from([1, 2, 3]);
+        let b = HashSet::from([2, 3, 4]);
+        assert_eq!(sorted(a.symmetric_difference(&b)), [1, 4]);
+    }
+
+    #[test]
+    fn subset_superset_disjoint() {
+        let a = HashSet::from([1, 2]);
+        let b = HashSet::from([1, 2, 3]);
+        let c = HashSet::from([4]);
+        assert!(a.is_subset(&b));
+        assert!(!b.is_subset(&a));
+        assert!(b.is_superset(&a));
+        assert!(a.is_disjoint(&c));
+        assert!(!a.is_disjoint(&b));
+        assert!(HashSet::new().is_subset(&c));
+    }
+
+    #[test]
+    fn operators() {
+        let a = HashSet::from([1, 2, 3]);
+        let b = HashSet::from([2, 3, 4]);
+        assert_eq!(&a | &b, HashSet::from([1, 2, 3, 4]));
+        assert_eq!(&a & &b, HashSet::from([2, 3]));
+        assert_eq!(&a - &b, HashSet::from([1]));
+        assert_eq!(&a ^ &b, HashSet::from([1, 4]));
+    }
+
+    #[test]
+    fn retain() {
+        let mut set: HashSet<_> = (0..10).collect();
+        set.retain(|&value| value % 2 == 0);
+        assert_eq!(sorted(set.iter()), [0, 2, 4, 6, 8]);
+    }
+
+    #[test]
+    fn debug() {
+        assert_eq!(format!("{:?}", HashSet::from([1])), "{1}");
+    }
+}
@@ -0,0 +1,263 @@
+//! A hash map for read-mostly data, published as immutable snapshots.
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crossbeam_epoch::{self as epoch, Atomic, Owned};
+
+use crate::HashMap;
+
+
+/// A `HashMap` which readers see as a series of immutable versions.
+///
+/// `snapshot` returns the current version as an `Arc`, without ever
+/// blocking: it can be read and iterated for as long as it is held, while
+/// writers publish newer versions. Writers take turns: each one edits a
+/// copy of the current version, and publishes it in one atomic swap, so
+/// readers see either none or all of its changes.
+///
+/// Every write copies the whole map, which is what keeps reads free of any
+/// lock or reference count on the entries. Batch changes into few writes.
+pub struct SnapshotMap<K, V, S = RandomState> {
+    /// Never null. Readers clone the `Arc` under an epoch guard, so that a
+    /// writer replacing it can only drop its own reference once they are
+    /// done.
+    current: Atomic<Arc<HashMap<K, V, S>>>,
+    writer: Mutex<()>,
+}
+
+
+impl<K, V> SnapshotMap<K, V, RandomState> {
+    pub fn new() -> Self {
+        SnapshotMap::from(HashMap::new())
+    }
+}
+
+
+impl<K, V, S> SnapshotMap<K, V, S> {
+    /// Creates an empty map which will use `hash_builder` to hash keys.
+    pub fn with_hasher(hash_builder: S) -> Self {
+        SnapshotMap::from(HashMap::with_hasher(hash_builder))
+    }
+
+    /// Returns the current version of the map. Later writes don't change
+    /// it.
+    pub fn snapshot(&self) -> Arc<HashMap<K, V, S>> {
+        let guard = epoch::pin();
+        let current = self.current.load(Acquire, &guard);
+        // SAFETY: `current` is never null, and a replaced version isn't
+        // dropped while `guard` is pinned.
+        Arc::clone(unsafe { current.deref() })
+    }
+}
+
+
+impl<K, V, S> SnapshotMap<K, V, S>
+where
+  K: Hash + Eq + Clone + Send + Sync + 'static,
+  V: Clone + Send + Sync + 'static,
+  S: BuildHasher + Clone + Send + Sync + 'static,
+{
+  /// Starts a new version from a copy of the current one. It is published
+  /// by `Writer::publish`, and discarded if dropped before that. Other
+  /// writers wait until then.
+  pub fn write(&self) -> Writer<'_, K, V, S> {
+    let lock = self.writer.lock().expect("writer lock poisoned");
+    let map = HashMap::clone(&self.snapshot());
+    Writer { owner: self, _lock: lock, map }
+  }
+
+  /// Publishes a new version made by `f` from a copy of the current one.
+  pub fn update<F: FnOnce(&mut HashMap<K, V, S>)>(&self, f: F) {
+    let mut writer = self.write();
+    f(&mut writer);
+    writer.publish();
+  }
+
+  /// Publishes `map` as the new version, replacing everything.
+  pub fn replace(&self, map: HashMap<K, V, S>) {
+    let _lock = self.writer.lock().expect("writer lock poisoned");
+    self.publish(map);
+  }
+
+  fn publish(&self, map: HashMap<K, V, S>) {
+    let guard = epoch::pin();
+    let old = self.current.swap(Owned::new(Arc::new(map)), AcqRel, &guard);
+    // SAFETY: `old` is unreachable now, and replaced by this thread only.
+    unsafe { guard.defer_destroy(old) };
+  }
+}
+
+
+impl<K, V, S> From<HashMap<K, V, S>> for SnapshotMap<K, V, S> {
+    fn from(map: HashMap<K, V, S>) -> Self {
+        SnapshotMap { current: Atomic::new(Arc::new(map)), writer: Mutex::new(()) }
+    }
+}
+
+
+impl<K, V, S> Drop for SnapshotMap<K, V, S> {
+    fn drop(&mut self) {
+        // SAFETY: no other thread can reach the map anymore, and its current
+        // version isn't pending reclamation.
+        unsafe { drop(self.current.load(Relaxed, epoch::unprotected()).into_owned()) }
+    }
+}
+
+
+impl<K, V> Default for SnapshotMap<K, V, RandomState> {
+    fn default() -> Self {
+        SnapshotMap::new()
+    }
+}
+
+
+impl<K, V, S> fmt::Debug for SnapshotMap<K, V, S>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.snapshot().fmt(f)
+    }
+}
+
+
+/// The next version of a `SnapshotMap`, being written. It derefs to the
+/// `HashMap` to edit.
+pub struct Writer<'a, K: 'a, V: 'a, S: 'a> {
+    owner: &'a SnapshotMap<K, V, S>,
+    _lock: MutexGuard<'a, ()>,
+    map: HashMap<K, V, S>,
+}
+
+
+impl<'a, K, V, S> Writer<'a, K, V, S>
+where
+    K: Hash + Eq + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+    S: BuildHasher + Clone + Send + Sync + 'static,
+{
+    /// Makes this version the current one.
+    pub fn publish(self) {
+        self.owner.publish(self.map);
+    }
+}
+
+
+impl<'a, K, V, S> Deref for Writer<'a, K, V, S> {
+    type Target = HashMap<K, V, S>;
+    fn deref(&self) -> &HashMap<K, V, S> {
+        &self.map
+    }
+}
+
+
+impl<'a, K, V, S> DerefMut for Writer<'a, K, V, S> {
+    fn deref_mut(&mut self) -> &mut HashMap<K, V, S> {
+        &mut self.map
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use std::thread;
+
+    #[test]
+    fn snapshot() {
+        let map = SnapshotMap::new();
+        map.update(|m| {
+            m.insert("a", 1);
+        });
+        let before = map.snapshot();
+        map.update(|m| {
+            m.insert("a", 2);
+            m.insert("b", 3);
+        });
+        assert_eq!(before.len(), 1);
+        assert_eq!(before["a"], 1);
+        assert_eq!(map.snapshot()["a"], 2);
+        assert_eq!(map.snapshot().len(), 2);
+    }
+
+    #[test]
+    fn write() {
+        let map = SnapshotMap::new();
+        let mut writer = map.write();
+        writer.insert(1, 1);
+        writer.insert(2, 2);
+        assert!(map.snapshot().is_empty());
+        writer.publish();
+        assert_eq!(map.snapshot().len(), 2);
+
+        let mut writer = map.write();
+        writer.clear();
+        drop(writer);
+        assert_eq!(map.snapshot().len(), 2);
+    }
+
+    #[test]
+    fn replace() {
+        let map = SnapshotMap::new();
+        map.update(|m| {
+            m.insert(1, 1);
+        });
+        map.replace(vec![(2, 2)].into_iter().collect());
+        assert!(!map.snapshot().contains_key(&1));
+        assert_eq!(map.snapshot()[&2], 2);
+    }
+
+    #[test]
+    fn debug() {
+        let map = SnapshotMap::from(vec![("a", 1)].into_iter().collect::<HashMap<_, _>>());
+        assert_eq!(format!("{:?}", map), r#"{"a": 1}"#);
+    }
+
+    #[test]
+    fn threads() {
+        // Every version maps all keys to the same value, so a reader seeing
+        // anything else saw a write half done.
+        let map = SnapshotMap::new();
+        map.update(|m| m.extend((0..100).map(|k| (k, 0))));
+        let done = AtomicBool::new(false);
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                let (map, done) = (&map, &done);
+                scope.spawn(move || {
+                    while !done.load(Relaxed) {
+                        let snapshot = map.snapshot();
+                        let first = snapshot[&0];
+                        assert_eq!(snapshot.len(), 100);
+                        assert!(snapshot.iter().all(|(_, &v)| v == first));
+                    }
+                });
+            }
+            for _ in 0..4 {
+                let map = &map;
+                scope.spawn(move || {
+                    for _ in 0..100 {
+                        map.update(|m| {
+                            for v in m.values_mut() {
+                                *v += 1;
+                            }
+                        });
+                    }
+                });
+            }
+            scope.spawn(|| {
+                while map.snapshot()[&0] < 400 {
+                    thread::yield_now();
+                }
+                done.store(true, Relaxed);
+            });
+        });
+        assert!(map.snapshot().iter().all(|(_, &v)| v == 400));
+    }
+}
@@ -0,0 +1,24 @@
+//! A random number generator for tests, shared with the integration tests
+//! through a `#[path]` attribute.
+
+
+/// xorshift64*, good enough to pick operations and reproducible from a seed.
+pub(crate) struct Rng(u64);
+
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub(crate) fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
@@ -0,0 +1,148 @@
+//! Differential tests: random operation sequences are run against both
+//! `hashmap::HashMap` and `std::collections::HashMap`, which serves as the
+//! model, and the two must agree after every step.
+
+extern crate hashmap;
+
+use std::collections::HashMap as Model;
+
+use hashmap::{GrowthPolicy, HashMap};
+
+#[path = "../src/test_rng.rs"]
+mod test_rng;
+
+use test_rng::Rng;
+
+
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    Insert(u32, u32),
+    Get(u32),
+    Remove(u32),
+    ContainsKey(u32),
+    Iter,
+    Len,
+}
+
+
+fn random_op(rng: &mut Rng, keys: u64) -> Op {
+    let key = rng.below(keys) as u32;
+    match rng.below(100) {
+        0..=39 => Op::Insert(key, rng.next() as u32),
+        40..=64 => Op::Get(key),
+        65..=89 => Op::Remove(key),
+        90..=96 => Op::ContainsKey(key),
+        97 | 98 => Op::Len,
+        // Iterating is linear in the map size, so keep it rare.
+        _ => Op::Iter,
+    }
+}
+
+
+/// Runs `steps` random operations on keys in `0..keys`, starting from the
+/// empty `map`, and panics with the seed and step on the first
+/// disagreement with the model.
+fn run(seed: u64, steps: usize, keys: u64, mut map: HashMap<u32, u32>) {
+    let mut rng = Rng::new(seed);
+    let mut model = Model::new();
+
+    for step in 0..steps {
+        let op = random_op(&mut rng, keys);
+        macro_rules! check {
+            ($actual:expr, $expected:expr) => {
+                assert_eq!($actual, $expected, "seed {}, step {}: {:?}", seed, step, op)
+            };
+        }
+
+        match op {
+            Op::Insert(k, v) => check!(map.insert(k, v), model.insert(k, v)),
+            Op::Get(k) => check!(map.get(&k), model.get(&k)),
+            Op::Remove(k) => check!(map.remove(&k), model.remove(&k)),
+            Op::ContainsKey(k) => check!(map.contains_key(&k), model.contains_key(&k)),
+            Op::Iter => {
+                let mut entries: Vec<_> = map.iter().map(|(&k, &v)| (k, v)).collect();
+                let mut expected: Vec<_> = model.iter().map(|(&k, &v)| (k, v)).collect();
+                entries.sort();
+                expected.sort();
+                check!(entries, expected);
+            }
+            Op::Len => check!(map.len(), model.len()),
+        }
+        check!(map.len(), model.len());
+        check!(map.is_empty(), model.is_empty());
+    }
+}
+
+
+#[test]
+fn few_keys() {
+    for seed in 0..100 {
+        run(seed, 1_000, 8, HashMap::new());
+    }
+}
+
+#[test]
+fn some_keys() {
+    for seed in 0..50 {
+        run(seed, 2_000, 128, HashMap::new());
+    }
+}
+
+#[test]
+fn many_keys() {
+    for seed in 0..10 {
+        run(seed, 10_000, 4_096, HashMap::new());
+    }
+}
+
+fn incremental() -> HashMap<u32, u32> {
+    let mut map = HashMap::new();
+    map.set_incremental_resize(true);
+    map
+}
+
+#[test]
+fn incremental_resize() {
+    for seed in 0..50 {
+        run(seed, 2_000, 128, incremental());
+    }
+    for seed in 0..10 {
+        run(seed, 10_000, 4_096, incremental());
+    }
+}
+
+#[test]
+fn growth_policies() {
+    let policies = [
+        GrowthPolicy { max_load_factor: 0.95, ..GrowthPolicy::default() },
+        GrowthPolicy { max_load_factor: 0.5, ..GrowthPolicy::default() },
+        GrowthPolicy { growth_multiplier: 4.0, min_buckets: 32, ..GrowthPolicy::default() },
+        GrowthPolicy { shrink_threshold: Some(0.25), ..GrowthPolicy::default() },
+    ];
+    for &policy in &policies {
+        for seed in 0..20 {
+            let mut map = HashMap::new();
+            map.set_growth_policy(policy);
+            run(seed, 2_000, 128, map);
+        }
+        for seed in 0..5 {
+            let mut map = incremental();
+            map.set_growth_policy(policy);
+            run(seed, 10_000, 4_096, map);
+        }
+    }
+}
+
+#[test]
+fn cached_hashes() {
+    for seed in 0..50 {
+        let mut map = HashMap::new();
+        map.set_cache_hashes(true);
+        run(seed, 2_000, 128, map);
+    }
+    for seed in 0..10 {
+        let mut map = incremental();
+        map.set_cache_hashes(true);
+        run(seed, 10_000, 4_096, map);
+    }
+}